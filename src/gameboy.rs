@@ -1,28 +1,142 @@
 use std::io::Read;
-use std::fs::File;
-use std::io;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use cpu::CPU;
+pub use cpu::{CpuError, CpuErrorKind, IllegalOpcodePolicy};
 use memory::Memory;
+use interrupts::Interrupt;
+use cartridge::{Cartridge, CartridgeError, CartridgeHeader, RtcClock};
+pub use model::Model;
+pub use joypad::Button;
+pub use screen::Renderer;
+pub use screenshot::{ImageFormat, Palette};
 
 mod cpu;
 mod registers;
 mod memory;
 mod instruction;
+pub mod interrupts;
+pub mod cartridge;
+mod model;
+mod mbc;
+mod joypad;
+mod serial;
+mod timer;
+mod apu;
+mod screen;
+mod screenshot;
+mod dma;
+
+//Clock speed of the DMG in T-cycles per second, and T-cycles per machine cycle
+pub const CLOCK_SPEED: u64 = 4_194_304;
+const T_CYCLES_PER_M_CYCLE: u64 = 4;
+
+//One frame is 154 lines of 456 dots each
+pub const CYCLES_PER_FRAME: u64 = 70224;
+
+//How often changed battery backed RAM is written out while running, about once a second
+const SAVE_INTERVAL_FRAMES: u64 = 60;
+
+//Why run stopped early
+#[derive(Debug)]
+pub enum RunError {
+    Cpu(CpuError),
+    //A periodic write of the save file failed
+    Save(io::Error)
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Cpu(error) => write!(f, "{}", error),
+            RunError::Save(error) => write!(f, "Could not write save file: {}", error)
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+impl From<CpuError> for RunError {
+    fn from(error: CpuError) -> Self {
+        RunError::Cpu(error)
+    }
+}
 
 pub struct Gameboy {
     cpu: CPU,
-    memory: Memory
+    memory: Memory,
+    model: Model,
+    rtc_clock: RtcClock,
+    //Last rumble motor state reported to the frontend
+    rumble: bool,
+    rumble_callback: Option<Box<dyn FnMut(bool)>>,
+    //.sav file next to the ROM, for cartridges with a battery
+    save_path: Option<PathBuf>,
+    //Directory for save files instead of the ROM's own
+    save_dir: Option<PathBuf>,
+    //Colors used for the 4 shades in screenshots
+    palette: Palette,
+    //Multiple of real time that run paces frames to, unthrottled when None
+    speed: Option<f64>,
+    //Total T-cycles elapsed since power on
+    clock: u64
+}
+
+impl Default for Gameboy {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Gameboy {
     pub fn new() -> Self {
+        Gameboy::with_model(Model::default())
+    }
+
+    pub fn with_model(model: Model) -> Self {
+        Gameboy::with_renderer(model, Renderer::default())
+    }
+
+    //The pixel FIFO renderer is slower but handles registers changed in the middle of a line
+    pub fn with_renderer(model: Model, renderer: Renderer) -> Self {
+        let mut memory = Memory::new();
+        memory.set_model(model);
+        memory.screen.set_renderer(renderer);
         Self {
             cpu: CPU::new(),
-            memory: Memory::new()
+            memory,
+            model,
+            rtc_clock: RtcClock::default(),
+            rumble: false,
+            rumble_callback: None,
+            save_path: None,
+            save_dir: None,
+            palette: Palette::default(),
+            speed: Some(1.0),
+            clock: 0
         }
     }
 
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    //Start at the cartridge entry point with the CPU and hardware registers set up as the boot ROM
+    //would have left them. Call after loading the cartridge, since the flags depend on its header.
+    pub fn skip_boot(&mut self) {
+        let header_checksum = self.cartridge_header().map_or(0, |header| header.header_checksum);
+        self.cpu.set_registers(self.model.post_boot_registers(header_checksum));
+        for (address, value) in self.model.post_boot_io_registers() {
+            self.memory.set_io_register(address, value);
+        }
+        self.memory.unmap_boot_rom();
+    }
+
     pub fn load_boot_rom(&mut self, path: &str) -> io::Result<()> {
         let mut rom_file = File::open(path)?;
         let mut rom_data = Vec::new();
@@ -32,10 +146,325 @@ impl Gameboy {
         Ok(())
     }
 
-    pub fn run(&mut self) {
-        loop {
-            self.cpu.cycle(&mut self.memory);
+    //Load a ROM, along with its save file if the cartridge has a battery and one exists
+    pub fn load_cartridge(&mut self, path: &str) -> Result<(), CartridgeError> {
+        let mut cartridge = Cartridge::load(path)?;
+        cartridge.set_rtc_clock(self.rtc_clock);
+        self.save_path = None;
+        if cartridge.header.cartridge_type.has_battery() {
+            let save_path = Path::new(path).with_extension("sav");
+            let save_path = match self.save_dir {
+                Some(ref dir) => dir.join(save_path.file_name().unwrap_or_default()),
+                None => save_path
+            };
+            match fs::read(&save_path) {
+                Ok(data) => cartridge.load_save_data(&data),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => {},
+                Err(error) => return Err(error.into())
+            }
+            self.save_path = Some(save_path);
+        }
+        self.memory.load_cartridge(cartridge);
+        Ok(())
+    }
+
+    //Keep save files in a directory of their own. Applies to cartridges loaded afterwards
+    pub fn set_save_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.save_dir = Some(dir.into());
+    }
+
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    //Write battery backed RAM and clock state to the save file. Does nothing for cartridges without a battery
+    pub fn save(&mut self) -> io::Result<()> {
+        if let (Some(path), Some(cartridge)) = (&self.save_path, self.memory.cartridge_mut()) {
+            //A failed write leaves the RAM marked as changed so the next flush tries again
+            fs::write(path, cartridge.save_data())?;
+            cartridge.mark_saved();
+        }
+        Ok(())
+    }
+
+    //Write the save file only if battery backed RAM changed since the last write
+    pub fn flush_save(&mut self) -> io::Result<()> {
+        if self.memory.cartridge().is_some_and(|cartridge| cartridge.ram_dirty()) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    //Choose whether a cartridge real time clock follows the host clock or emulated time
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.rtc_clock = clock;
+        if let Some(cartridge) = self.memory.cartridge_mut() {
+            cartridge.set_rtc_clock(clock);
+        }
+    }
+
+    //Called with the new motor state whenever a rumble cartridge switches its motor on or off
+    pub fn on_rumble(&mut self, callback: impl FnMut(bool) + 'static) {
+        self.rumble_callback = Some(Box::new(callback));
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    //Shade indices 0-3 of the last frame, 160 per row and 144 rows
+    pub fn framebuffer(&self) -> &[u8] {
+        self.memory.screen.framebuffer()
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    //The last frame encoded as an image file
+    pub fn screenshot_data(&self, format: ImageFormat) -> Vec<u8> {
+        screenshot::encode(self.framebuffer(), &self.palette, format)
+    }
+
+    //Write the last frame to a .png or .ppm file
+    pub fn screenshot(&self, path: &str) -> io::Result<()> {
+        let format = ImageFormat::from_path(Path::new(path)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "screenshots must be .png or .ppm files")
+        })?;
+        fs::write(path, self.screenshot_data(format))
+    }
+
+    pub fn cartridge_header(&self) -> Option<&CartridgeHeader> {
+        self.memory.cartridge().map(|cartridge| &cartridge.header)
+    }
+
+    //Raise an interrupt request from outside the emulated hardware, e.g. a serial link driven by the host
+    pub fn request_interrupt(&mut self, interrupt: Interrupt) {
+        self.memory.interrupts.request(interrupt);
+    }
+
+    //Press or release a button. Pressing one in a selected row requests the joypad interrupt
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        self.memory.joypad.set_button(button, pressed, &mut self.memory.interrupts);
+    }
+
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.cpu.illegal_opcode_policy = policy;
+    }
+
+    //Log the CPU registers and the bytes at PC before every instruction
+    pub fn set_trace(&mut self, output: impl Write + 'static) {
+        self.cpu.trace = Some(Box::new(output));
+    }
+
+    //Pace run to a multiple of real time, or run as fast as possible with None
+    pub fn set_speed(&mut self, speed: Option<f64>) {
+        self.speed = speed;
+    }
+
+    //Execute one instruction, returning the number of T-cycles it took
+    pub fn step(&mut self) -> Result<u64, CpuError> {
+        //In CGB double speed mode the CPU runs twice as many machine cycles in the same time
+        let t_cycles_per_m_cycle = if self.memory.double_speed() { T_CYCLES_PER_M_CYCLE / 2 } else { T_CYCLES_PER_M_CYCLE };
+        let m_cycles = self.cpu.cycle(&mut self.memory)? as u64;
+        let cycles = m_cycles * t_cycles_per_m_cycle;
+        self.memory.tick(m_cycles, cycles);
+        self.clock += cycles;
+        if let Some(cartridge) = self.memory.cartridge_mut() {
+            cartridge.tick(cycles);
+            let rumble = cartridge.rumble();
+            if rumble != self.rumble {
+                self.rumble = rumble;
+                if let Some(callback) = self.rumble_callback.as_mut() {
+                    callback(rumble);
+                }
+            }
+        }
+        Ok(cycles)
+    }
+
+    //Run until the clock reaches the start of the next frame
+    pub fn run_frame(&mut self) -> Result<(), CpuError> {
+        let frame_end = (self.clock / CYCLES_PER_FRAME + 1) * CYCLES_PER_FRAME;
+        while self.clock < frame_end {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    //Run in real time until the CPU or a periodic save fails
+    pub fn run(&mut self) -> Result<(), RunError> {
+        self.run_for(None)
+    }
+
+    //Run at the set speed for a number of frames, or until something fails when None. Changed save
+    //data is written about once a second, call flush_save afterwards to write the rest
+    pub fn run_for(&mut self, frames: Option<u64>) -> Result<(), RunError> {
+        let frame_duration = self.speed.map(|speed| {
+            Duration::from_secs_f64(CYCLES_PER_FRAME as f64 / CLOCK_SPEED as f64 / speed)
+        });
+        let mut remaining_frames = frames;
+        while remaining_frames != Some(0) {
+            remaining_frames = remaining_frames.map(|frames| frames - 1);
+            let frame_start = Instant::now();
+            self.run_frame()?;
+            if (self.clock / CYCLES_PER_FRAME).is_multiple_of(SAVE_INTERVAL_FRAMES) {
+                self.flush_save().map_err(RunError::Save)?;
+            }
+            if let Some(remaining) = frame_duration.and_then(|duration| duration.checked_sub(frame_start.elapsed())) {
+                thread::sleep(remaining);
+            }
+        }
+        Ok(())
+    }
+
+}
+
+//Last chance to keep progress for callers that never flush. Errors cannot be reported from here, so
+//anyone who cares should call flush_save first. An unchanged clock needs no write, its footer
+//timestamp already lets it catch up on the next load
+impl Drop for Gameboy {
+    fn drop(&mut self) {
+        let _ = self.flush_save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use cartridge::header_checksum;
+
+    #[test]
+    fn skip_boot_starts_at_entry_point() {
+        let mut gameboy = Gameboy::new();
+        gameboy.load_cartridge("roms/zelda_rom.gb").unwrap();
+        gameboy.skip_boot();
+        assert_eq!(gameboy.memory.read_8(0xFF40),0x91);
+        assert_eq!(gameboy.memory.read_8(0xFF0F),0xE1);
+        for _ in 0..1000 {
+            gameboy.step().unwrap();
+        }
+        assert!(gameboy.clock > 0);
+    }
+
+    #[test]
+    fn rumble_callback_follows_motor() {
+        let mut rom = vec![0; 0x8000];
+        //MBC5+RUMBLE
+        rom[0x0147] = 0x1C;
+        //LD A,0x08; LD (0x4000),A; LD A,0x00; LD (0x4000),A; JR -2
+        rom[0x0100..0x010C].copy_from_slice(&[0x3E, 0x08, 0xEA, 0x00, 0x40, 0x3E, 0x00, 0xEA, 0x00, 0x40, 0x18, 0xFE]);
+        rom[0x014D] = header_checksum(&rom);
+        let mut gameboy = Gameboy::new();
+        gameboy.memory.load_cartridge(Cartridge::from_bytes(rom).unwrap());
+        gameboy.skip_boot();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&events);
+        gameboy.on_rumble(move |on| recorded.borrow_mut().push(on));
+        gameboy.step().unwrap();
+        gameboy.step().unwrap();
+        assert!(gameboy.rumble());
+        gameboy.step().unwrap();
+        gameboy.step().unwrap();
+        assert!(!gameboy.rumble());
+        assert_eq!(*events.borrow(), vec![true, false]);
+    }
+
+    //Directory of its own under the system temp dir, removed again when the test ends even if it fails
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("gameboyemu_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TestDir(path)
         }
     }
 
-}
\ No newline at end of file
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn battery_ram_persists_to_save_file() {
+        let directory = TestDir::new("battery_ram_persists_to_save_file");
+        let rom_path = directory.0.join("battery.gb");
+        let mut rom = vec![0; 0x8000];
+        //MBC1+RAM+BATTERY with 8 KiB of RAM
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        rom[0x014D] = header_checksum(&rom);
+        fs::write(&rom_path, &rom).unwrap();
+        let save_path = directory.0.join("battery.sav");
+
+        let mut gameboy = Gameboy::new();
+        gameboy.load_cartridge(rom_path.to_str().unwrap()).unwrap();
+        assert_eq!(gameboy.save_path(), Some(save_path.as_path()));
+        //Nothing is written until the RAM changes
+        gameboy.flush_save().unwrap();
+        assert!(!save_path.exists());
+        gameboy.memory.write_8(0x0000,0x0A);
+        gameboy.memory.write_8(0xA010,0x42);
+        gameboy.flush_save().unwrap();
+        assert_eq!(fs::read(&save_path).unwrap().len(), 0x2000);
+
+        let mut gameboy = Gameboy::new();
+        gameboy.load_cartridge(rom_path.to_str().unwrap()).unwrap();
+        gameboy.memory.write_8(0x0000,0x0A);
+        assert_eq!(gameboy.memory.read_8(0xA010),0x42);
+
+        let save_dir = directory.0.join("saves");
+        let mut gameboy = Gameboy::new();
+        gameboy.set_save_dir(&save_dir);
+        gameboy.load_cartridge(rom_path.to_str().unwrap()).unwrap();
+        assert_eq!(gameboy.save_path(), Some(save_dir.join("battery.sav").as_path()));
+    }
+
+    #[test]
+    fn failed_save_is_reported() {
+        let directory = TestDir::new("failed_save_is_reported");
+        let rom_path = directory.0.join("battery.gb");
+        let mut rom = vec![0; 0x8000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        rom[0x014D] = header_checksum(&rom);
+        fs::write(&rom_path, &rom).unwrap();
+
+        let mut gameboy = Gameboy::new();
+        //The save directory does not exist, so writing fails
+        gameboy.set_save_dir(directory.0.join("missing"));
+        gameboy.load_cartridge(rom_path.to_str().unwrap()).unwrap();
+        gameboy.memory.write_8(0x0000,0x0A);
+        gameboy.memory.write_8(0xA000,0x01);
+        assert!(gameboy.flush_save().is_err());
+        gameboy.skip_boot();
+        gameboy.set_speed(None);
+        assert!(matches!(gameboy.run_for(Some(SAVE_INTERVAL_FRAMES)), Err(RunError::Save(_))));
+    }
+
+    #[test]
+    fn run_for_stops_after_frames() {
+        let mut gameboy = Gameboy::new();
+        gameboy.load_cartridge("roms/zelda_rom.gb").unwrap();
+        gameboy.skip_boot();
+        gameboy.set_speed(None);
+        gameboy.run_for(Some(3)).unwrap();
+        assert_eq!(gameboy.clock / CYCLES_PER_FRAME,3);
+    }
+
+    #[test]
+    fn set_button_is_visible_on_p1() {
+        let mut gameboy = Gameboy::new();
+        gameboy.memory.write_8(0xFF00,0x10);
+        gameboy.set_button(Button::Start, true);
+        assert_eq!(gameboy.memory.read_8(0xFF00),0xD7);
+        assert!(gameboy.memory.interrupts.is_requested(Interrupt::Joypad));
+        gameboy.set_button(Button::Start, false);
+        assert_eq!(gameboy.memory.read_8(0xFF00),0xDF);
+    }
+}
@@ -0,0 +1,118 @@
+const APU_REGISTERS_LOCATION: (u16, u16) = (0xFF10, 0xFF2F);
+const WAVE_RAM_LOCATION: (u16, u16) = (0xFF30, 0xFF3F);
+const NR52_ADDRESS: u16 = 0xFF26;
+//Last register cleared when the APU is switched off, NR51
+const LAST_CLEARED_ADDRESS: u16 = 0xFF25;
+
+//Trigger bit of NR14, NR24, NR34 and NR44
+const TRIGGER_BIT: u8 = 0x80;
+const POWER_BIT: u8 = 0x80;
+const CHANNEL_STATUS_BITS: u8 = 0x0F;
+
+//Bits that always read as 1 for each register from NR10 to 0xFF2F. Write only fields such as
+//frequencies and length timers read back as 1 too
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // unused, NR21-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // unused, NR41-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF // unused
+];
+
+//Sound registers at 0xFF10-0xFF3F. Only the register file is modelled, no audio is generated
+pub struct Apu {
+    registers: [u8; 0x20],
+    wave_ram: [u8; 0x10],
+    powered: bool,
+    //Channel on flags reported in the lower bits of NR52
+    channels: u8
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 0x20],
+            wave_ram: [0; 0x10],
+            powered: false,
+            channels: 0
+        }
+    }
+
+    //Works while the APU is powered off, and for NR52 also sets the read only channel flags
+    pub fn set_register(&mut self, address: u16, value: u8) {
+        match address {
+            NR52_ADDRESS => {
+                self.powered = value & POWER_BIT != 0;
+                self.channels = value & CHANNEL_STATUS_BITS;
+            },
+            _ if address >= WAVE_RAM_LOCATION.0 => self.wave_ram[(address - WAVE_RAM_LOCATION.0) as usize] = value,
+            _ => self.registers[(address - APU_REGISTERS_LOCATION.0) as usize] = value
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            NR52_ADDRESS => {
+                READ_MASKS[(address - APU_REGISTERS_LOCATION.0) as usize]
+                    | if self.powered { POWER_BIT } else { 0 }
+                    | self.channels
+            },
+            _ if address <= APU_REGISTERS_LOCATION.1 => {
+                let index = (address - APU_REGISTERS_LOCATION.0) as usize;
+                self.registers[index] | READ_MASKS[index]
+            },
+            _ => self.wave_ram[(address - WAVE_RAM_LOCATION.0) as usize]
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            //Switching off clears every register and stops all channels
+            NR52_ADDRESS => {
+                self.powered = value & POWER_BIT != 0;
+                if !self.powered {
+                    self.registers[..=(LAST_CLEARED_ADDRESS - APU_REGISTERS_LOCATION.0) as usize].fill(0);
+                    self.channels = 0;
+                }
+            },
+            //Wave RAM stays accessible while the APU is off
+            _ if address >= WAVE_RAM_LOCATION.0 => self.wave_ram[(address - WAVE_RAM_LOCATION.0) as usize] = value,
+            _ if !self.powered => {},
+            _ => {
+                let index = (address - APU_REGISTERS_LOCATION.0) as usize;
+                self.registers[index] = value;
+                //NRx4 registers are 5 apart starting at NR14
+                if index % 5 == 4 && index < 0x14 && value & TRIGGER_BIT != 0 {
+                    self.channels |= 1 << (index / 5);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_masks_and_power() {
+        let mut apu = Apu::new();
+        apu.write(0xFF26,0x80);
+        apu.write(0xFF11,0x00);
+        assert_eq!(apu.read(0xFF11),0x3F);
+        apu.write(0xFF12,0xF3);
+        assert_eq!(apu.read(0xFF12),0xF3);
+        apu.write(0xFF19,0x80);
+        assert_eq!(apu.read(0xFF26),0xF2);
+        apu.write(0xFF30,0x12);
+        apu.write(0xFF26,0x00);
+        assert_eq!(apu.read(0xFF26),0x70);
+        assert_eq!(apu.read(0xFF12),0x00);
+        //Registers ignore writes while powered off, wave RAM does not
+        apu.write(0xFF12,0xF3);
+        assert_eq!(apu.read(0xFF12),0x00);
+        assert_eq!(apu.read(0xFF30),0x12);
+        assert_eq!(apu.read(0xFF27),0xFF);
+    }
+}
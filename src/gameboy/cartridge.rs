@@ -0,0 +1,470 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::mbc::{self, MemoryBankController};
+pub use super::mbc::RtcClock;
+
+const HEADER_LOCATION: (usize, usize) = (0x0100, 0x014F);
+const TITLE_LOCATION: (usize, usize) = (0x0134, 0x0143);
+const MANUFACTURER_CODE_LOCATION: (usize, usize) = (0x013F, 0x0142);
+const CGB_FLAG_ADDRESS: usize = 0x0143;
+const NEW_LICENSEE_CODE_LOCATION: (usize, usize) = (0x0144, 0x0145);
+const SGB_FLAG_ADDRESS: usize = 0x0146;
+const CARTRIDGE_TYPE_ADDRESS: usize = 0x0147;
+const ROM_SIZE_ADDRESS: usize = 0x0148;
+const RAM_SIZE_ADDRESS: usize = 0x0149;
+const DESTINATION_CODE_ADDRESS: usize = 0x014A;
+const OLD_LICENSEE_CODE_ADDRESS: usize = 0x014B;
+const VERSION_ADDRESS: usize = 0x014C;
+const HEADER_CHECKSUM_ADDRESS: usize = 0x014D;
+const GLOBAL_CHECKSUM_LOCATION: (usize, usize) = (0x014E, 0x014F);
+
+//Old licensee code telling the real code is in the new licensee field
+const USE_NEW_LICENSEE_CODE: u8 = 0x33;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug)]
+pub enum CartridgeError {
+    Io(io::Error),
+    //The file is too small to contain a header
+    TooSmall(usize),
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    //The file is smaller than the ROM size declared in the header
+    RomSizeMismatch { declared: usize, actual: usize },
+    HeaderChecksumMismatch { expected: u8, actual: u8 },
+    UnsupportedMbc(MbcKind)
+}
+
+impl fmt::Display for CartridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io(error) => write!(f, "Could not read ROM: {}", error),
+            CartridgeError::TooSmall(size) => write!(f, "ROM is {} bytes, too small to contain a cartridge header", size),
+            CartridgeError::InvalidRomSize(code) => write!(f, "Invalid ROM size code {:#04x}", code),
+            CartridgeError::InvalidRamSize(code) => write!(f, "Invalid RAM size code {:#04x}", code),
+            CartridgeError::RomSizeMismatch { declared, actual } => {
+                write!(f, "Header declares {} bytes of ROM but the file is {} bytes", declared, actual)
+            },
+            CartridgeError::HeaderChecksumMismatch { expected, actual } => {
+                write!(f, "Header checksum is {:#04x} but the header sums to {:#04x}", expected, actual)
+            },
+            CartridgeError::UnsupportedMbc(kind) => write!(f, "Memory bank controller {:?} is not supported", kind)
+        }
+    }
+}
+
+impl std::error::Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(error: io::Error) -> Self {
+        CartridgeError::Io(error)
+    }
+}
+
+//Value of the CGB flag at 0x0143
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    DmgOnly,
+    //0x80, runs on both DMG and CGB
+    Enhanced,
+    //0xC0, refuses to run on a DMG
+    CgbOnly
+}
+
+//Memory bank controller wired into the cartridge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MbcKind {
+    RomOnly,
+    Mbc1,
+    Mbc2,
+    Mbc3,
+    Mbc5,
+    Mbc6,
+    Mbc7,
+    Mmm01,
+    PocketCamera,
+    Tama5,
+    HuC3,
+    HuC1,
+    Unknown(u8)
+}
+
+//Cartridge type byte at 0x0147
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8
+}
+
+impl CartridgeType {
+    pub fn mbc(&self) -> MbcKind {
+        match self.code {
+            0x00 | 0x08 | 0x09 => MbcKind::RomOnly,
+            0x01..=0x03 => MbcKind::Mbc1,
+            0x05 | 0x06 => MbcKind::Mbc2,
+            0x0B..=0x0D => MbcKind::Mmm01,
+            0x0F..=0x13 => MbcKind::Mbc3,
+            0x19..=0x1E => MbcKind::Mbc5,
+            0x20 => MbcKind::Mbc6,
+            0x22 => MbcKind::Mbc7,
+            0xFC => MbcKind::PocketCamera,
+            0xFD => MbcKind::Tama5,
+            0xFE => MbcKind::HuC3,
+            0xFF => MbcKind::HuC1,
+            code => MbcKind::Unknown(code)
+        }
+    }
+
+    pub fn has_ram(&self) -> bool {
+        matches!(self.code, 0x02 | 0x03 | 0x08 | 0x09 | 0x0C | 0x0D | 0x10 | 0x12 | 0x13 | 0x1A | 0x1B | 0x1D | 0x1E | 0x22 | 0xFF)
+    }
+
+    pub fn has_battery(&self) -> bool {
+        matches!(self.code, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF)
+    }
+
+    pub fn has_timer(&self) -> bool {
+        matches!(self.code, 0x0F | 0x10)
+    }
+
+    pub fn has_rumble(&self) -> bool {
+        matches!(self.code, 0x1C..=0x1E)
+    }
+}
+
+//Publisher of the game, either the old one byte code or the new two character code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Licensee {
+    Old(u8),
+    New(String)
+}
+
+//Cartridge header found at 0x0100-0x014F
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CartridgeHeader {
+    pub title: String,
+    //Only present on later cartridges, which shortened the title to make room for it
+    pub manufacturer_code: Option<String>,
+    pub cgb_support: CgbSupport,
+    pub sgb_support: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size_code: u8,
+    pub ram_size_code: u8,
+    //False for cartridges sold in Japan
+    pub overseas: bool,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16
+}
+
+impl CartridgeHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, CartridgeError> {
+        if rom.len() <= HEADER_LOCATION.1 {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb_support = match rom[CGB_FLAG_ADDRESS] {
+            0xC0 => CgbSupport::CgbOnly,
+            0x80 => CgbSupport::Enhanced,
+            _ => CgbSupport::DmgOnly
+        };
+
+        //The manufacturer code overlaps the end of the old 16 character title, so only treat it as one
+        //on CGB aware cartridges where it is made up of uppercase letters and digits
+        let manufacturer_bytes = &rom[MANUFACTURER_CODE_LOCATION.0..=MANUFACTURER_CODE_LOCATION.1];
+        let has_manufacturer_code = cgb_support != CgbSupport::DmgOnly
+            && manufacturer_bytes.iter().all(|byte| byte.is_ascii_uppercase() || byte.is_ascii_digit());
+        let manufacturer_code = if has_manufacturer_code {
+            Some(String::from_utf8_lossy(manufacturer_bytes).into_owned())
+        } else {
+            None
+        };
+        let title_end = if has_manufacturer_code {
+            MANUFACTURER_CODE_LOCATION.0
+        } else if cgb_support != CgbSupport::DmgOnly {
+            CGB_FLAG_ADDRESS
+        } else {
+            TITLE_LOCATION.1 + 1
+        };
+        let title = rom[TITLE_LOCATION.0..title_end].iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect();
+
+        let licensee = match rom[OLD_LICENSEE_CODE_ADDRESS] {
+            USE_NEW_LICENSEE_CODE => {
+                let code = &rom[NEW_LICENSEE_CODE_LOCATION.0..=NEW_LICENSEE_CODE_LOCATION.1];
+                Licensee::New(String::from_utf8_lossy(code).into_owned())
+            },
+            code => Licensee::Old(code)
+        };
+
+        let header = Self {
+            title,
+            manufacturer_code,
+            cgb_support,
+            sgb_support: rom[SGB_FLAG_ADDRESS] == 0x03,
+            cartridge_type: CartridgeType { code: rom[CARTRIDGE_TYPE_ADDRESS] },
+            rom_size_code: rom[ROM_SIZE_ADDRESS],
+            ram_size_code: rom[RAM_SIZE_ADDRESS],
+            overseas: rom[DESTINATION_CODE_ADDRESS] != 0x00,
+            licensee,
+            version: rom[VERSION_ADDRESS],
+            header_checksum: rom[HEADER_CHECKSUM_ADDRESS],
+            global_checksum: (rom[GLOBAL_CHECKSUM_LOCATION.0] as u16) << 8 | rom[GLOBAL_CHECKSUM_LOCATION.1] as u16
+        };
+        header.rom_size()?;
+        header.ram_size()?;
+        Ok(header)
+    }
+
+    //ROM size in bytes, 32 KiB shifted left by the size code
+    pub fn rom_size(&self) -> Result<usize, CartridgeError> {
+        match self.rom_size_code {
+            0x00..=0x08 => Ok((2 * ROM_BANK_SIZE) << self.rom_size_code),
+            code => Err(CartridgeError::InvalidRomSize(code))
+        }
+    }
+
+    //External RAM size in bytes. MBC2 has its RAM built in and reports 0 here
+    pub fn ram_size(&self) -> Result<usize, CartridgeError> {
+        match self.ram_size_code {
+            0x00 => Ok(0),
+            0x01 => Ok(0x800),
+            0x02 => Ok(RAM_BANK_SIZE),
+            0x03 => Ok(4 * RAM_BANK_SIZE),
+            0x04 => Ok(16 * RAM_BANK_SIZE),
+            0x05 => Ok(8 * RAM_BANK_SIZE),
+            code => Err(CartridgeError::InvalidRamSize(code))
+        }
+    }
+}
+
+//Checksum of the header bytes 0x0134-0x014C as computed by the boot ROM
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_LOCATION.0..=VERSION_ADDRESS].iter()
+        .fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+//Sum of every byte in the ROM except the global checksum itself
+pub fn global_checksum(rom: &[u8]) -> u16 {
+    rom.iter().enumerate()
+        .filter(|(address, _)| *address != GLOBAL_CHECKSUM_LOCATION.0 && *address != GLOBAL_CHECKSUM_LOCATION.1)
+        .fold(0u16, |checksum, (_, byte)| checksum.wrapping_add(*byte as u16))
+}
+
+pub struct Cartridge {
+    pub header: CartridgeHeader,
+    rom: Vec<u8>,
+    mbc: Box<dyn MemoryBankController>,
+    //Set when battery backed RAM changed since the last save
+    ram_dirty: bool
+}
+
+impl Cartridge {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CartridgeError> {
+        Cartridge::from_bytes(fs::read(path)?)
+    }
+
+    //Parse and validate a ROM image. The header checksum must match since the boot ROM refuses to start
+    //otherwise, but the global checksum is never checked by hardware and only reported
+    pub fn from_bytes(rom: Vec<u8>) -> Result<Self, CartridgeError> {
+        let header = CartridgeHeader::parse(&rom)?;
+        let checksum = header_checksum(&rom);
+        if checksum != header.header_checksum {
+            return Err(CartridgeError::HeaderChecksumMismatch { expected: header.header_checksum, actual: checksum });
+        }
+        let declared = header.rom_size()?;
+        if rom.len() < declared {
+            return Err(CartridgeError::RomSizeMismatch { declared, actual: rom.len() });
+        }
+        let mbc = mbc::new_mbc(&header, &rom)?;
+        Ok(Self {
+            header,
+            rom,
+            mbc,
+            ram_dirty: false
+        })
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    //0x0000-0x7FFF
+    pub fn read_rom(&self, address: u16) -> u8 {
+        self.mbc.read_rom(&self.rom, address)
+    }
+
+    pub fn write_rom(&mut self, address: u16, value: u8) {
+        self.mbc.write_rom(address, value);
+    }
+
+    //0xA000-0xBFFF
+    pub fn read_ram(&self, address: u16) -> u8 {
+        self.mbc.read_ram(address)
+    }
+
+    pub fn write_ram(&mut self, address: u16, value: u8) {
+        self.mbc.write_ram(address, value);
+        self.ram_dirty |= self.header.cartridge_type.has_battery();
+    }
+
+    pub fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    //Contents of a .sav file: the raw RAM, followed by the clock state on cartridges with a real time clock
+    pub fn save_data(&mut self) -> Vec<u8> {
+        let mut data = self.mbc.ram().to_vec();
+        if let Some(rtc) = self.save_rtc() {
+            data.extend_from_slice(&rtc);
+        }
+        data
+    }
+
+    //Call once the save data has been written out
+    pub fn mark_saved(&mut self) {
+        self.ram_dirty = false;
+    }
+
+    //Restore a .sav file. Files of a different size are accepted so saves from other emulators still load
+    pub fn load_save_data(&mut self, data: &[u8]) {
+        let ram = self.mbc.ram_mut();
+        let length = ram.len().min(data.len());
+        ram[..length].copy_from_slice(&data[..length]);
+        if data.len() > length {
+            self.load_rtc(&data[length..]);
+        }
+        self.ram_dirty = false;
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        self.mbc.tick(cycles);
+    }
+
+    pub fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.mbc.set_rtc_clock(clock);
+    }
+
+    //Real time clock state to be stored after the save RAM, for cartridges with a clock
+    pub fn save_rtc(&mut self) -> Option<Vec<u8>> {
+        self.mbc.save_rtc()
+    }
+
+    pub fn load_rtc(&mut self, data: &[u8]) {
+        self.mbc.load_rtc(data);
+    }
+
+    pub fn rumble(&self) -> bool {
+        self.mbc.rumble()
+    }
+
+    pub fn global_checksum_valid(&self) -> bool {
+        global_checksum(&self.rom) == self.header.global_checksum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rom(title: &[u8], cgb_flag: u8, cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[TITLE_LOCATION.0..TITLE_LOCATION.0 + title.len()].copy_from_slice(title);
+        rom[CGB_FLAG_ADDRESS] = cgb_flag;
+        rom[CARTRIDGE_TYPE_ADDRESS] = cartridge_type;
+        rom[OLD_LICENSEE_CODE_ADDRESS] = USE_NEW_LICENSEE_CODE;
+        rom[NEW_LICENSEE_CODE_LOCATION.0] = b'0';
+        rom[NEW_LICENSEE_CODE_LOCATION.1] = b'1';
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum(&rom);
+        let checksum = global_checksum(&rom);
+        rom[GLOBAL_CHECKSUM_LOCATION.0] = (checksum >> 8) as u8;
+        rom[GLOBAL_CHECKSUM_LOCATION.1] = (checksum & 0xFF) as u8;
+        rom
+    }
+
+    #[test]
+    fn parses_dmg_header() {
+        let cartridge = Cartridge::from_bytes(test_rom(b"TETRIS", 0x00, 0x00)).unwrap();
+        assert_eq!(cartridge.header.title, "TETRIS");
+        assert_eq!(cartridge.header.manufacturer_code, None);
+        assert_eq!(cartridge.header.cgb_support, CgbSupport::DmgOnly);
+        assert_eq!(cartridge.header.licensee, Licensee::New("01".to_string()));
+        assert_eq!(cartridge.header.cartridge_type.mbc(), MbcKind::RomOnly);
+        assert_eq!(cartridge.header.rom_size().unwrap(), 0x8000);
+        assert!(cartridge.global_checksum_valid());
+    }
+
+    #[test]
+    fn parses_manufacturer_code() {
+        let header = CartridgeHeader::parse(&test_rom(b"POKEMON_SLVAAXE", 0x80, 0x10)).unwrap();
+        assert_eq!(header.title, "POKEMON_SLV");
+        assert_eq!(header.manufacturer_code, Some("AAXE".to_string()));
+        assert_eq!(header.cgb_support, CgbSupport::Enhanced);
+        assert_eq!(header.cartridge_type.mbc(), MbcKind::Mbc3);
+        assert!(header.cartridge_type.has_timer() && header.cartridge_type.has_battery());
+    }
+
+    #[test]
+    fn rejects_bad_header_checksum() {
+        let mut rom = test_rom(b"TETRIS", 0x00, 0x00);
+        rom[HEADER_CHECKSUM_ADDRESS] ^= 0xFF;
+        assert!(matches!(Cartridge::from_bytes(rom), Err(CartridgeError::HeaderChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn rejects_truncated_rom() {
+        let mut rom = test_rom(b"TETRIS", 0x00, 0x00);
+        rom[ROM_SIZE_ADDRESS] = 0x01;
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum(&rom);
+        assert!(matches!(Cartridge::from_bytes(rom), Err(CartridgeError::RomSizeMismatch { .. })));
+    }
+
+    #[test]
+    fn rejects_unsupported_mbc() {
+        let rom = test_rom(b"CAMERA", 0x00, 0xFC);
+        assert!(matches!(Cartridge::from_bytes(rom), Err(CartridgeError::UnsupportedMbc(MbcKind::PocketCamera))));
+    }
+
+    #[test]
+    fn save_data_round_trip() {
+        let mut rom = test_rom(b"POKEMON_SLV", 0x80, 0x10);
+        rom[RAM_SIZE_ADDRESS] = 0x03;
+        rom[HEADER_CHECKSUM_ADDRESS] = header_checksum(&rom);
+        let mut cartridge = Cartridge::from_bytes(rom.clone()).unwrap();
+        cartridge.set_rtc_clock(RtcClock::Emulated);
+        cartridge.write_rom(0x0000, 0x0A);
+        cartridge.write_rom(0x4000, 0x02);
+        cartridge.write_ram(0xA123, 0x77);
+        assert!(cartridge.ram_dirty());
+        let data = cartridge.save_data();
+        assert!(cartridge.ram_dirty());
+        cartridge.mark_saved();
+        assert!(!cartridge.ram_dirty());
+        //32 KiB of RAM followed by the 48 byte clock footer
+        assert_eq!(data.len(), 4 * RAM_BANK_SIZE + 48);
+
+        let mut restored = Cartridge::from_bytes(rom).unwrap();
+        restored.load_save_data(&data);
+        restored.write_rom(0x0000, 0x0A);
+        restored.write_rom(0x4000, 0x02);
+        assert_eq!(restored.read_ram(0xA123), 0x77);
+    }
+
+    #[test]
+    fn parses_zelda_header() {
+        let cartridge = Cartridge::load("roms/zelda_rom.gb").unwrap();
+        assert_eq!(cartridge.header.title, "ZELDA");
+        assert_eq!(cartridge.header.cartridge_type.mbc(), MbcKind::Mbc1);
+        assert!(cartridge.header.cartridge_type.has_battery());
+        assert_eq!(cartridge.header.rom_size().unwrap(), 512 * 1024);
+        assert_eq!(cartridge.header.ram_size().unwrap(), 8 * 1024);
+        assert!(cartridge.global_checksum_valid());
+    }
+}
@@ -1,77 +1,293 @@
 use super::registers;
 use super::instruction::*;
 use super::Memory;
+use super::interrupts::Interrupt;
+use std::fmt;
+use std::io::Write;
+
+//Why the CPU stopped executing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuErrorKind {
+    //One of the opcodes without a defined instruction, which lock up the CPU on hardware
+    IllegalOpcode,
+    //The instruction decoded, but with an operand the CPU cannot execute it with
+    UnsupportedOperand(String)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuError {
+    //Address of the failing instruction, including any 0xCB prefix
+    pub pc: u16,
+    pub opcode: Vec<u8>,
+    pub kind: CpuErrorKind
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let opcode: String = self.opcode.iter().map(|byte| format!("{:02X}", byte)).collect();
+        match self.kind {
+            CpuErrorKind::IllegalOpcode => write!(f, "Illegal opcode 0x{} at PC {:#06x}", opcode, self.pc),
+            CpuErrorKind::UnsupportedOperand(ref description) => {
+                write!(f, "Unsupported operand {} for opcode 0x{} at PC {:#06x}", description, opcode, self.pc)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+//What the CPU does when it executes an illegal opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IllegalOpcodePolicy {
+    //Hang forever without servicing interrupts, as on hardware
+    Lock,
+    //Leave PC at the opcode and return a CpuError to the caller
+    #[default]
+    Error
+}
+
+#[allow(clippy::upper_case_acronyms)]
 pub struct CPU {
-    registers: registers::Registers
+    registers: registers::Registers,
+    //Interrupt master enable, and whether EI has asked for it to be set after the next instruction
+    ime: bool,
+    ime_scheduled: bool,
+    //Low power states entered by HALT and STOP
+    halted: bool,
+    stopped: bool,
+    //Set when HALT is executed with IME=0 and an interrupt pending, so the next opcode byte is read twice
+    halt_bug: bool,
+    //Set when an illegal opcode has hung the CPU
+    locked: bool,
+    pub illegal_opcode_policy: IllegalOpcodePolicy,
+    //Receives one line of CPU state before each instruction when set
+    pub trace: Option<Box<dyn Write>>
 }
 
+//Machine cycles taken to dispatch an interrupt: two wait states, pushing PC and jumping to the vector
+const INTERRUPT_DISPATCH_CYCLES: u8 = 5;
+
 impl CPU {
     pub fn new() -> Self {
         Self {
-            registers: registers::Registers::new()
+            registers: registers::Registers::new(),
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            stopped: false,
+            halt_bug: false,
+            locked: false,
+            illegal_opcode_policy: IllegalOpcodePolicy::default(),
+            trace: None
         }
     }
 
-    pub fn cycle(&mut self, memory: &mut Memory) {
+    pub fn set_registers(&mut self, registers: registers::Registers) {
+        self.registers = registers;
+    }
+
+    //Registers and the 4 bytes at PC, in the format used by Gameboy Doctor. Tracing stops if the output fails
+    fn write_trace(&mut self, memory: &Memory) {
+        let r = &self.registers;
+        let pc_memory: Vec<String> = (0..4).map(|offset| format!("{:02X}", memory.read_8(r.pc.wrapping_add(offset)))).collect();
+        let line = format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{}",
+            r.a, u8::from(&r.f), r.b, r.c, r.d, r.e, r.h, r.l, r.sp, r.pc, pc_memory.join(","));
+        if let Some(ref mut trace) = self.trace {
+            if writeln!(trace, "{}", line).is_err() {
+                self.trace = None;
+            }
+        }
+    }
+
+    //Execute a single instruction and return the number of machine cycles it took
+    pub fn cycle(&mut self, memory: &mut Memory) -> Result<u8, CpuError> {
+
+        if self.locked {
+            return Ok(1);
+        }
+
+        //STOP is only left once a joypad line goes low, which requests the joypad interrupt
+        if self.stopped {
+            if !memory.interrupts.is_requested(Interrupt::Joypad) {
+                return Ok(1);
+            }
+            self.stopped = false;
+        }
+
+        //HALT is left as soon as any enabled interrupt is requested, even when IME is not set
+        if self.halted {
+            if !memory.interrupts.is_pending() {
+                return Ok(1);
+            }
+            self.halted = false;
+        }
+
+        if self.ime && memory.interrupts.is_pending() {
+            return Ok(self.dispatch_interrupt(memory));
+        }
+
+        //EI only takes effect after the instruction following it has executed
+        let enable_ime = self.ime_scheduled;
+
+        if self.trace.is_some() {
+            self.write_trace(memory);
+        }
 
         //Read one byte from memory at the current pc as an instruction.
-        let mut instruction_byte = memory.read_8(self.registers.pc);
+        let pc = self.registers.pc;
+        let mut instruction_byte = memory.read_8(pc);
+
+        //With the HALT bug PC fails to increment after the opcode fetch. Moving PC back one byte makes
+        //operand reads and the next PC start from the opcode byte again.
+        if self.halt_bug {
+            self.registers.pc = self.registers.pc.wrapping_sub(1);
+            self.halt_bug = false;
+        }
 
         //If instruction byte is 0xCB, it is a prefixed instruction. Handle separately
         let prefixed = instruction_byte == 0xCB;
         if prefixed {
-            self.registers.pc += 1;
+            self.registers.pc = self.registers.pc.wrapping_add(1);
             instruction_byte = memory.read_8(self.registers.pc);
         }
-        let next_pc = if let Some(instruction) = Instruction::decode(instruction_byte,prefixed) {
-            self.execute(instruction, memory)
-        } else {
-            let description = format!("0x{}{:x}", if prefixed { "CB" } else { "" }, instruction_byte);
-            panic!("Unkown instruction found for: {}. PC: {:#06x}", description,self.registers.pc)
+        let opcode = if prefixed { vec![0xCB, instruction_byte] } else { vec![instruction_byte] };
+        let instruction = match Instruction::decode(instruction_byte,prefixed) {
+            Some(instruction) => instruction,
+            None => return self.illegal_opcode(pc, opcode)
+        };
+        let cycles = instruction.cycles(self.branch_taken(&instruction));
+        let next_pc = match self.execute_checked(instruction, memory) {
+            Ok(next_pc) => next_pc,
+            Err(kind) => {
+                self.registers.pc = pc;
+                return Err(CpuError { pc, opcode, kind });
+            }
         };
         self.registers.pc = next_pc;
+        if enable_ime && self.ime_scheduled {
+            self.ime = true;
+            self.ime_scheduled = false;
+        }
+        Ok(cycles)
+    }
+
+    //Execute an instruction, putting every register back if it fails part way through an operand
+    fn execute_checked(&mut self, instruction: Instruction, memory: &mut Memory) -> Result<u16, CpuErrorKind> {
+        let registers = self.registers;
+        self.execute(instruction, memory).inspect_err(|_| self.registers = registers)
     }
 
+    fn illegal_opcode(&mut self, pc: u16, opcode: Vec<u8>) -> Result<u8, CpuError> {
+        match self.illegal_opcode_policy {
+            IllegalOpcodePolicy::Lock => {
+                self.locked = true;
+                Ok(1)
+            },
+            IllegalOpcodePolicy::Error => Err(CpuError { pc, opcode, kind: CpuErrorKind::IllegalOpcode })
+        }
+    }
+
+    //Push PC and jump to the vector of the highest priority pending interrupt.
+    //The interrupt is chosen after the upper byte of PC is pushed, so if that push overwrites IE
+    //and cancels every pending interrupt, execution continues at 0x0000 instead.
+    fn dispatch_interrupt(&mut self, memory: &mut Memory) -> u8 {
+        self.ime = false;
+        self.ime_scheduled = false;
+        let pc = self.registers.pc;
+        self.push8((pc >> 8) as u8, memory);
+        let interrupt = memory.interrupts.highest_pending();
+        self.push8((pc & 0x00FF) as u8, memory);
+        self.registers.pc = match interrupt {
+            Some(interrupt) => {
+                memory.interrupts.acknowledge(interrupt);
+                interrupt.vector()
+            },
+            None => 0x0000
+        };
+        INTERRUPT_DISPATCH_CYCLES
+    }
 
-    pub fn execute(&mut self, instruction: Instruction, memory: &mut Memory) -> u16{
+    //Whether a conditional jump, call or return will branch with the current flags
+    fn branch_taken(&self, instruction: &Instruction) -> bool {
         match instruction {
-            Instruction::ADD8(ref register) => {
-                let new_value = self.add8(register);
-                self.registers.a = new_value;
-                self.registers.pc.wrapping_add(1)
-                },
-            Instruction::NOP => self.registers.pc.wrapping_add(1),
+            Instruction::JR(condition, _) |
+            Instruction::JP(condition, _) |
+            Instruction::CALL(condition) |
+            Instruction::RET(condition) => self.condition_met(condition),
+            _ => false
+        }
+    }
+
+
+    //Execute a decoded instruction and return the address of the next one
+    pub fn execute(&mut self, instruction: Instruction, memory: &mut Memory) -> Result<u16, CpuErrorKind> {
+        let next_pc = self.registers.pc.wrapping_add(instruction.size());
+        Ok(match instruction {
+            Instruction::ADD8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.add8(value, false);
+                next_pc
+            },
+            Instruction::ADC8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.add8(value, self.registers.f.carry);
+                next_pc
+            },
+            Instruction::SUB8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.sub8(value, false);
+                next_pc
+            },
+            Instruction::SBC8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.sub8(value, self.registers.f.carry);
+                next_pc
+            },
+            Instruction::AND8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.and8(value);
+                next_pc
+            },
+            Instruction::XOR8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.xor8(value);
+                next_pc
+            },
+            Instruction::OR8(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.registers.a = self.or8(value);
+                next_pc
+            },
+            Instruction::CP(ref source) => {
+                let value = self.read_source8(source, memory);
+                self.cp(value);
+                next_pc
+            },
+            Instruction::NOP => next_pc,
             Instruction::LD16(source, target) => {
                 let source_val = match source {
                     LoadSource16::D16 => {
-                        memory.read_16(self.registers.pc+1)
+                        memory.read_16(self.registers.pc.wrapping_add(1))
                     },
                     LoadSource16::Reg(ref register) => self.registers.get_16(register),
-                    //_ => (panic!("{:?}",source))
+                    LoadSource16::SPOffset => {
+                        let offset = memory.read_8(self.registers.pc.wrapping_add(1)) as i8;
+                        self.add_sp(offset)
+                    }
                 };
                 match target {
                     LoadTarget16::Reg(ref register) => {
                         self.registers.set_16(register,source_val);
                     },
-                    //_ => (panic!("{:?}",target))
-                }
-                match source {
-                    LoadSource16::D16 => self.registers.pc.wrapping_add(3),
-                    _ => self.registers.pc.wrapping_add(1)
+                    LoadTarget16::AddressA16 => {
+                        let address = memory.read_16(self.registers.pc.wrapping_add(1));
+                        memory.write_16(address,source_val);
+                    }
                 }
+                next_pc
             },
             Instruction::LD8(source, target) => {
-                let source_val = match source {
-                    LoadSource8::D8 => {
-                        memory.read_8(self.registers.pc+1)
-                    },
-                    LoadSource8::Reg(ref register) => {
-                        self.registers.get_8(register)
-                    },
-                    LoadSource8::Address(ref register) => {
-                        memory.read_8(self.registers.get_16(register))
-                    }
-                };
+                let source_val = self.read_source8(&source, memory);
                 match target {
                     LoadTarget8::Address(ref register) => {
                         memory.write_8(self.registers.get_16(register),source_val);
@@ -82,133 +298,293 @@ impl CPU {
                     LoadTarget8::AddressDec(ref register) => {
                         let curr_address = self.registers.get_16(register);
                         memory.write_8(curr_address,source_val);
-                        self.registers.set_16(register,curr_address-1);
+                        self.registers.set_16(register,curr_address.wrapping_sub(1));
                     },
                     LoadTarget8::OffsetAddress(ref register) => {
                         let curr_address = 0xFF00 + self.registers.get_8(register) as u16;
                         memory.write_8(curr_address,source_val)
                     },
                     LoadTarget8::OffsetA8 => {
-                        let curr_address = 0xFF00 + memory.read_8(self.registers.pc+1) as u16;
+                        let curr_address = 0xFF00 + memory.read_8(self.registers.pc.wrapping_add(1)) as u16;
                         memory.write_8(curr_address,source_val)
                     },
                     LoadTarget8::AddressInc(ref register) => {
                         let curr_address = self.registers.get_16(register);
                         memory.write_8(curr_address,source_val);
-                        self.registers.set_16(register,curr_address+1);
+                        self.registers.set_16(register,curr_address.wrapping_add(1));
                     },
-                    LoadTarget8::AddressD8 => {
-                        let curr_address = memory.read_16(self.registers.pc+1);
+                    LoadTarget8::AddressA16 => {
+                        let curr_address = memory.read_16(self.registers.pc.wrapping_add(1));
                         memory.write_8(curr_address,source_val)
-                    },
-                    _ => panic!("Error target {:?} not implemented",target)
-                }
-                match source {
-                    LoadSource8::D8 => self.registers.pc.wrapping_add(2),
-                    _ => {
-                        match target {
-                            LoadTarget8::OffsetA8 => self.registers.pc.wrapping_add(2),
-                            LoadTarget8::AddressD8 => self.registers.pc.wrapping_add(3),
-                            _ => self.registers.pc.wrapping_add(1)
-                        }
                     }
                 }
+                next_pc
             },
-            Instruction::XOR8(ref register) => {
-                let new_value = self.xor8(register);
-                self.registers.a = new_value;
-                self.registers.pc.wrapping_add(1)
+            Instruction::BIT(ref source, index) => {
+                let value = self.read_source8(source, memory);
+                self.bit_test(value, index);
+                next_pc
             },
-            Instruction::BIT(source, index) => {
-                match source {
-                    LoadSource8::Reg(ref register) => self.bit_test(register, index),
-                    _ => panic!("BIT source {:?} not implemented",source)
-                }
-                self.registers.pc.wrapping_add(1)
-            },
-            Instruction::JR(condition,source) => {
+            Instruction::JR(ref condition,source) => {
                 let source_val:i8 = match source {
-                    LoadSource8::D8 => memory.read_8(self.registers.pc+1) as i8,
-                    _ => panic!("JR Source {:?} not implemented",source)
-                };
-                let should_jump = match condition {
-                    JumpCondition::NZ => !self.registers.f.zero,
-                    JumpCondition::Z => self.registers.f.zero
+                    LoadSource8::D8 => memory.read_8(self.registers.pc.wrapping_add(1)) as i8,
+                    _ => return Err(CpuErrorKind::UnsupportedOperand(format!("{:?}",source)))
                 };
+                let should_jump = self.condition_met(condition);
                 self.jr(should_jump,source_val)
             },
-            Instruction::INC8(ref register) => {
-                self.registers.set_8(register,self.registers.get_8(register) + 1);
-                self.registers.pc.wrapping_add(1)
+            Instruction::JP(ref condition, source) => {
+                let should_jump = self.condition_met(condition);
+                match source {
+                    LoadSource16::D16 if should_jump => memory.read_16(self.registers.pc.wrapping_add(1)),
+                    LoadSource16::Reg(ref register) => self.registers.get_16(register),
+                    _ => next_pc
+                }
+            },
+            Instruction::INC8(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.inc8(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::DEC8(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.dec8(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
             },
             Instruction::INC16(ref register) => {
-                self.registers.set_16(register,self.registers.get_16(register) + 1);
-                self.registers.pc.wrapping_add(1)
-            },
-            //Push PC onto the stack and then jump to address specified by next 2 bytes
-            Instruction::CALL(condition) => {
-                match condition {
-                    CallCondition::None => {
-                        self.push16(self.registers.pc,memory);
-                        memory.read_16(self.registers.pc+1)
-                    }
+                self.registers.set_16(register,self.registers.get_16(register).wrapping_add(1));
+                next_pc
+            },
+            Instruction::DEC16(ref register) => {
+                self.registers.set_16(register,self.registers.get_16(register).wrapping_sub(1));
+                next_pc
+            },
+            Instruction::ADD16(ref register) => {
+                let value = self.registers.get_16(register);
+                let new_value = self.add16(value);
+                self.registers.set_16(&Register16::HL, new_value);
+                next_pc
+            },
+            Instruction::ADDSP => {
+                let offset = memory.read_8(self.registers.pc.wrapping_add(1)) as i8;
+                self.registers.sp = self.add_sp(offset);
+                next_pc
+            },
+            //Push address of the next instruction onto the stack and then jump to address specified by next 2 bytes
+            Instruction::CALL(ref condition) => {
+                if self.condition_met(condition) {
+                    self.push16(next_pc,memory);
+                    memory.read_16(self.registers.pc.wrapping_add(1))
+                } else {
+                    next_pc
+                }
+            },
+            Instruction::RST(vector) => {
+                self.push16(next_pc,memory);
+                vector
+            },
+            Instruction::RET(ref condition) => {
+                if self.condition_met(condition) {
+                    self.pop16(memory)
+                } else {
+                    next_pc
                 }
-                
             },
-            Instruction::RET => {
-                self.pop16(memory) + 3
+            Instruction::RETI => {
+                self.ime = true;
+                self.pop16(memory)
             },
             Instruction::PUSH(ref register) => {
                 let source_val = self.registers.get_16(register);
                 self.push16(source_val,memory);
-                self.registers.pc.wrapping_add(1)
-            },
-            Instruction::RL(ref source) => {
-                match source {
-                    LoadSource8::Reg(ref register) => {
-                        let mut source_val = self.registers.get_8(register);
-                        source_val = self.rl(source_val);
-                        self.registers.set_8(register,source_val);
-                        self.registers.pc.wrapping_add(1)
-                    },
-                    _ => panic!("Error RL source {:?} not implemented",source)
-                }
-               
+                next_pc
             },
             Instruction::POP(ref register) => {
                 let new_val = self.pop16(memory);
                 self.registers.set_16(register,new_val);
-                self.registers.pc.wrapping_add(1)
+                next_pc
+            },
+            Instruction::RL(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.rl(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
             },
-            Instruction::DEC8(ref register) => {
-                self.registers.set_8(register,self.registers.get_8(register)-1);
-                self.registers.pc.wrapping_add(1)
+            Instruction::RLC(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.rlc(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
             },
-            Instruction::CP(ref source) => {
-                let source_val = match source {
-                    LoadSource8::D8 => {
-                        memory.read_8(self.registers.pc+1)
-                    },
-                    _ => panic!("Error: CP source {:?} not implemented",source)
-                };
-                self.cp(source_val);
-                self.registers.pc.wrapping_add(2)
+            Instruction::RRC(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.rrc(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::RR(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.rr(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::SLA(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.sla(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::SRA(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.sra(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::SWAP(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.swap(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::SRL(ref source) => {
+                let value = self.read_source8(source, memory);
+                let new_value = self.srl(value);
+                self.write_source8(source, new_value, memory)?;
+                next_pc
+            },
+            Instruction::RES(ref source, index) => {
+                let value = self.read_source8(source, memory);
+                self.write_source8(source, value & !(1 << index), memory)?;
+                next_pc
+            },
+            Instruction::SET(ref source, index) => {
+                let value = self.read_source8(source, memory);
+                self.write_source8(source, value | (1 << index), memory)?;
+                next_pc
+            },
+            Instruction::RLCA => {
+                self.registers.a = self.rlc(self.registers.a);
+                self.registers.f.zero = false;
+                next_pc
+            },
+            Instruction::RRCA => {
+                self.registers.a = self.rrc(self.registers.a);
+                self.registers.f.zero = false;
+                next_pc
+            },
+            Instruction::RLA => {
+                self.registers.a = self.rl(self.registers.a);
+                self.registers.f.zero = false;
+                next_pc
+            },
+            Instruction::RRA => {
+                self.registers.a = self.rr(self.registers.a);
+                self.registers.f.zero = false;
+                next_pc
             },
-            _ => {
-                panic!("Error, instruction {:?} not implemented",instruction);
+            Instruction::DAA => {
+                self.daa();
+                next_pc
+            },
+            Instruction::CPL => {
+                self.registers.a = !self.registers.a;
+                self.registers.f.subtract = true;
+                self.registers.f.half_carry = true;
+                next_pc
+            },
+            Instruction::SCF => {
+                self.registers.f.subtract = false;
+                self.registers.f.half_carry = false;
+                self.registers.f.carry = true;
+                next_pc
+            },
+            Instruction::CCF => {
+                self.registers.f.subtract = false;
+                self.registers.f.half_carry = false;
+                self.registers.f.carry = !self.registers.f.carry;
+                next_pc
+            },
+            Instruction::DI => {
+                self.ime = false;
+                self.ime_scheduled = false;
+                next_pc
+            },
+            Instruction::EI => {
+                self.ime_scheduled = true;
+                next_pc
+            },
+            Instruction::HALT => {
+                if !self.ime && memory.interrupts.is_pending() {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+                next_pc
+            },
+            //STOP resets DIV. On CGB a prepared speed switch is performed instead of entering STOP mode
+            Instruction::STOP => {
+                memory.reset_div();
+                if memory.speed_switch_armed() {
+                    memory.switch_speed();
+                } else {
+                    self.stopped = true;
+                }
+                next_pc
             }
+        })
+    }
+
+    //Read the value of an 8 bit operand, applying any side effects on the address register
+    fn read_source8(&mut self, source: &LoadSource8, memory: &Memory) -> u8 {
+        match source {
+            LoadSource8::Reg(ref register) => self.registers.get_8(register),
+            LoadSource8::Address(ref register) => memory.read_8(self.registers.get_16(register)),
+            LoadSource8::D8 => memory.read_8(self.registers.pc.wrapping_add(1)),
+            LoadSource8::AddressInc(ref register) => {
+                let address = self.registers.get_16(register);
+                self.registers.set_16(register,address.wrapping_add(1));
+                memory.read_8(address)
+            },
+            LoadSource8::AddressDec(ref register) => {
+                let address = self.registers.get_16(register);
+                self.registers.set_16(register,address.wrapping_sub(1));
+                memory.read_8(address)
+            },
+            LoadSource8::OffsetAddress(ref register) => memory.read_8(0xFF00 + self.registers.get_8(register) as u16),
+            LoadSource8::OffsetA8 => memory.read_8(0xFF00 + memory.read_8(self.registers.pc.wrapping_add(1)) as u16),
+            LoadSource8::AddressA16 => memory.read_8(memory.read_16(self.registers.pc.wrapping_add(1)))
         }
-            
+    }
+
+    //Write back the result of a read-modify-write instruction to its operand
+    fn write_source8(&mut self, source: &LoadSource8, value: u8, memory: &mut Memory) -> Result<(), CpuErrorKind> {
+        match source {
+            LoadSource8::Reg(ref register) => self.registers.set_8(register,value),
+            LoadSource8::Address(ref register) => memory.write_8(self.registers.get_16(register),value),
+            _ => return Err(CpuErrorKind::UnsupportedOperand(format!("{:?}",source)))
         }
+        Ok(())
+    }
 
+    fn condition_met(&self, condition: &JumpCondition) -> bool {
+        match condition {
+            JumpCondition::NZ => !self.registers.f.zero,
+            JumpCondition::Z => self.registers.f.zero,
+            JumpCondition::NC => !self.registers.f.carry,
+            JumpCondition::C => self.registers.f.carry,
+            JumpCondition::Always => true
+        }
+    }
 
     fn cp(&mut self, value: u8) {
-        self.registers.f.zero = value == self.registers.a
+        self.sub8(value, false);
     }
 
     fn pop8(&mut self, memory: &Memory) -> u8 {
         let value = memory.read_8(self.registers.sp);
-        self.registers.sp += 1;
+        self.registers.sp = self.registers.sp.wrapping_add(1);
         value
     }
 
@@ -224,13 +600,65 @@ impl CPU {
         if self.registers.f.carry {
             rotated_val += 1;
         }
-        self.registers.f.carry = val & 0x80 != 0;
-        self.registers.f.zero = rotated_val == 0;
+        self.set_rotate_flags(rotated_val, val & 0x80 != 0);
+        rotated_val
+    }
+
+    fn rr(&mut self, val:u8) -> u8 {
+        let mut rotated_val = val >> 1;
+        if self.registers.f.carry {
+            rotated_val |= 0x80;
+        }
+        self.set_rotate_flags(rotated_val, val & 0x01 != 0);
+        rotated_val
+    }
+
+    fn rlc(&mut self, val:u8) -> u8 {
+        let rotated_val = val.rotate_left(1);
+        self.set_rotate_flags(rotated_val, val & 0x80 != 0);
+        rotated_val
+    }
+
+    fn rrc(&mut self, val:u8) -> u8 {
+        let rotated_val = val.rotate_right(1);
+        self.set_rotate_flags(rotated_val, val & 0x01 != 0);
         rotated_val
     }
-    
+
+    fn sla(&mut self, val:u8) -> u8 {
+        let shifted_val = val << 1;
+        self.set_rotate_flags(shifted_val, val & 0x80 != 0);
+        shifted_val
+    }
+
+    //Arithmetic shift keeps the sign bit in place
+    fn sra(&mut self, val:u8) -> u8 {
+        let shifted_val = (val >> 1) | (val & 0x80);
+        self.set_rotate_flags(shifted_val, val & 0x01 != 0);
+        shifted_val
+    }
+
+    fn srl(&mut self, val:u8) -> u8 {
+        let shifted_val = val >> 1;
+        self.set_rotate_flags(shifted_val, val & 0x01 != 0);
+        shifted_val
+    }
+
+    fn swap(&mut self, val:u8) -> u8 {
+        let swapped_val = val.rotate_left(4);
+        self.set_rotate_flags(swapped_val, false);
+        swapped_val
+    }
+
+    fn set_rotate_flags(&mut self, result: u8, carry: bool) {
+        self.registers.f.zero = result == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = carry;
+    }
+
     fn push8(&mut self, value:u8,memory: &mut Memory) {
-        self.registers.sp -= 1;
+        self.registers.sp = self.registers.sp.wrapping_sub(1);
         memory.write_8(self.registers.sp,value);
     }
 
@@ -238,21 +666,17 @@ impl CPU {
         self.push8((value >> 8) as u8, memory);
         self.push8((value & 0x00FF) as u8,memory);
     }
-    
+
     fn jr(&mut self, should_jump:bool,value:i8) -> u16 {
+        let next_pc = self.registers.pc.wrapping_add(2);
         if should_jump {
-            if value < 0 {
-                self.registers.pc - (-value) as u16 + 2
-            } else {
-                self.registers.pc + value as u16 + 2
-            }
+            next_pc.wrapping_add(value as i16 as u16)
         } else {
-            self.registers.pc.wrapping_add(2)
+            next_pc
         }
     }
 
-    fn xor8(&mut self, register:&Register8) -> u8 {
-        let value = self.registers.get_8(register);
+    fn xor8(&mut self, value: u8) -> u8 {
         let new_value = self.registers.a ^ value;
         self.registers.f.zero = new_value == 0;
         self.registers.f.subtract = false;
@@ -261,25 +685,120 @@ impl CPU {
         new_value
     }
 
-    fn bit_test(&mut self, register: &Register8, index:u8) {
-        let bit_value = (self.registers.get_8(register) >> index) & 0x01;
+    fn or8(&mut self, value: u8) -> u8 {
+        let new_value = self.registers.a | value;
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.carry = false;
+        self.registers.f.half_carry = false;
+        new_value
+    }
+
+    fn and8(&mut self, value: u8) -> u8 {
+        let new_value = self.registers.a & value;
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.carry = false;
+        self.registers.f.half_carry = true;
+        new_value
+    }
+
+    fn bit_test(&mut self, value: u8, index:u8) {
+        let bit_value = (value >> index) & 0x01;
         self.registers.f.zero = bit_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = true;
+    }
+
+    fn add8(&mut self, value: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let a = self.registers.a;
+        let new_value = a.wrapping_add(value).wrapping_add(carry);
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.carry = a as u16 + value as u16 + carry as u16 > 0xFF;
+        self.registers.f.half_carry = (a & 0xF) + (value & 0xF) + carry > 0xF;
+        new_value
+    }
+
+    fn sub8(&mut self, value: u8, carry: bool) -> u8 {
+        let carry = carry as u8;
+        let a = self.registers.a;
+        let new_value = a.wrapping_sub(value).wrapping_sub(carry);
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = true;
+        self.registers.f.carry = (a as u16) < value as u16 + carry as u16;
+        self.registers.f.half_carry = (a & 0xF) < (value & 0xF) + carry;
+        new_value
+    }
+
+    fn inc8(&mut self, value: u8) -> u8 {
+        let new_value = value.wrapping_add(1);
+        self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = value & 0xF == 0xF;
+        new_value
     }
 
-    fn add8(&mut self, register: &Register8) -> u8 {
-        let value = self.registers.get_8(&register);
-        let (new_value,did_overflow) = self.registers.a.overflowing_add(value);
+    fn dec8(&mut self, value: u8) -> u8 {
+        let new_value = value.wrapping_sub(1);
         self.registers.f.zero = new_value == 0;
+        self.registers.f.subtract = true;
+        self.registers.f.half_carry = value & 0xF == 0;
+        new_value
+    }
+
+    fn add16(&mut self, value: u16) -> u16 {
+        let hl = self.registers.get_16(&Register16::HL);
+        let (new_value, did_overflow) = hl.overflowing_add(value);
         self.registers.f.subtract = false;
         self.registers.f.carry = did_overflow;
-        self.registers.f.half_carry = (self.registers.a & 0xF) + (value & 0xF) > 0xF;
+        self.registers.f.half_carry = (hl & 0xFFF) + (value & 0xFFF) > 0xFFF;
         new_value
     }
+
+    //SP + s8, with flags computed on the lower byte as an unsigned addition
+    fn add_sp(&mut self, offset: i8) -> u16 {
+        let sp = self.registers.sp;
+        let value = offset as u8 as u16;
+        self.registers.f.zero = false;
+        self.registers.f.subtract = false;
+        self.registers.f.half_carry = (sp & 0xF) + (value & 0xF) > 0xF;
+        self.registers.f.carry = (sp & 0xFF) + value > 0xFF;
+        sp.wrapping_add(offset as i16 as u16)
+    }
+
+    //Adjust A back into binary coded decimal after an addition or subtraction
+    fn daa(&mut self) {
+        let mut a = self.registers.a;
+        let mut carry = self.registers.f.carry;
+        if !self.registers.f.subtract {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if self.registers.f.half_carry || a & 0x0F > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
+        } else {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if self.registers.f.half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        }
+        self.registers.a = a;
+        self.registers.f.zero = a == 0;
+        self.registers.f.half_carry = false;
+        self.registers.f.carry = carry;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::model::Model;
     #[test]
     fn test_rl() {
         let mut cpu = CPU::new();
@@ -292,4 +811,267 @@ mod tests {
         assert!(!cpu.registers.f.carry);
         assert!(!cpu.registers.f.zero);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_shifts() {
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.sra(0x81),0xC0);
+        assert!(cpu.registers.f.carry && !cpu.registers.f.zero);
+        assert_eq!(cpu.srl(0x01),0x00);
+        assert!(cpu.registers.f.carry && cpu.registers.f.zero);
+        assert_eq!(cpu.sla(0x40),0x80);
+        assert!(!cpu.registers.f.carry);
+        assert_eq!(cpu.swap(0xF1),0x1F);
+        assert!(!cpu.registers.f.carry && !cpu.registers.f.zero);
+    }
+    #[test]
+    fn test_prefixed_on_hl_address() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.set_16(&Register16::HL,0xC000);
+        memory.write_8(0xC000,0x0F);
+        cpu.execute(Instruction::SET(LoadSource8::Address(Register16::HL),7), &mut memory).unwrap();
+        cpu.execute(Instruction::RES(LoadSource8::Address(Register16::HL),0), &mut memory).unwrap();
+        assert_eq!(memory.read_8(0xC000),0x8E);
+        cpu.registers.f.carry = true;
+        cpu.execute(Instruction::BIT(LoadSource8::Address(Register16::HL),4), &mut memory).unwrap();
+        assert!(cpu.registers.f.zero && cpu.registers.f.half_carry && !cpu.registers.f.subtract);
+        assert!(cpu.registers.f.carry);
+        cpu.execute(Instruction::RR(LoadSource8::Address(Register16::HL)), &mut memory).unwrap();
+        assert_eq!(memory.read_8(0xC000),0xC7);
+        assert!(!cpu.registers.f.carry);
+    }
+    #[test]
+    fn test_cycle_counts_branches() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.pc = 0xC000;
+        //JR NZ +2 twice, first with Z set and then with Z cleared
+        memory.write_8(0xC000,0x20);
+        memory.write_8(0xC001,0x00);
+        memory.write_8(0xC002,0x20);
+        memory.write_8(0xC003,0x02);
+        cpu.registers.f.zero = true;
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),2);
+        assert_eq!(cpu.registers.pc,0xC002);
+        cpu.registers.f.zero = false;
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),3);
+        assert_eq!(cpu.registers.pc,0xC006);
+        //BIT 7 (HL)
+        memory.write_8(0xC006,0xCB);
+        memory.write_8(0xC007,0x7E);
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),3);
+        assert_eq!(cpu.registers.pc,0xC008);
+    }
+    #[test]
+    fn test_ei_delay_and_dispatch() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.sp = 0xFFFE;
+        cpu.registers.pc = 0xC000;
+        //EI, NOP, NOP with a VBlank interrupt already requested
+        memory.write_8(0xC000,0xFB);
+        memory.interrupts.write_ie(Interrupt::VBlank.bit() | Interrupt::Timer.bit());
+        memory.interrupts.request(Interrupt::Timer);
+        memory.interrupts.request(Interrupt::VBlank);
+        cpu.cycle(&mut memory).unwrap();
+        assert!(!cpu.ime);
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),1);
+        assert_eq!(cpu.registers.pc,0xC002);
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),5);
+        assert_eq!(cpu.registers.pc,0x0040);
+        assert_eq!(memory.read_16(cpu.registers.sp),0xC002);
+        assert!(!cpu.ime);
+        assert_eq!(memory.interrupts.highest_pending(),Some(Interrupt::Timer));
+        //RETI returns and re-enables interrupts without a delay
+        cpu.registers.pc = cpu.execute(Instruction::RETI, &mut memory).unwrap();
+        assert_eq!(cpu.registers.pc,0xC002);
+        assert!(cpu.ime);
+        cpu.cycle(&mut memory).unwrap();
+        assert_eq!(cpu.registers.pc,0x0050);
+    }
+    #[test]
+    fn test_di_cancels_pending_ei() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.pc = 0xC000;
+        memory.write_8(0xC000,0xFB);
+        memory.write_8(0xC001,0xF3);
+        cpu.cycle(&mut memory).unwrap();
+        cpu.cycle(&mut memory).unwrap();
+        cpu.cycle(&mut memory).unwrap();
+        assert!(!cpu.ime);
+    }
+    #[test]
+    fn test_halt_waits_for_interrupt() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.sp = 0xFFFE;
+        cpu.registers.pc = 0xC000;
+        cpu.ime = true;
+        memory.write_8(0xC000,0x76);
+        memory.interrupts.write_ie(Interrupt::Timer.bit());
+        cpu.cycle(&mut memory).unwrap();
+        assert!(cpu.halted);
+        for _ in 0..10 {
+            assert_eq!(cpu.cycle(&mut memory).unwrap(),1);
+            assert_eq!(cpu.registers.pc,0xC001);
+        }
+        memory.interrupts.request(Interrupt::Timer);
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),5);
+        assert_eq!(cpu.registers.pc,0x0050);
+        assert_eq!(memory.read_16(cpu.registers.sp),0xC001);
+    }
+    #[test]
+    fn test_halt_without_ime_resumes() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.pc = 0xC000;
+        memory.write_8(0xC000,0x76);
+        memory.write_8(0xC001,0x3C);
+        memory.interrupts.write_ie(Interrupt::Serial.bit());
+        cpu.cycle(&mut memory).unwrap();
+        cpu.cycle(&mut memory).unwrap();
+        assert!(cpu.halted);
+        memory.interrupts.request(Interrupt::Serial);
+        cpu.cycle(&mut memory).unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.registers.a,1);
+        assert_eq!(cpu.registers.pc,0xC002);
+        assert_eq!(memory.interrupts.highest_pending(),Some(Interrupt::Serial));
+    }
+    #[test]
+    fn test_halt_bug() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.pc = 0xC000;
+        //HALT, LD A 0x14 with an interrupt pending and IME=0 executes LD A 0x3E, then INC D (0x14)
+        memory.write_8(0xC000,0x76);
+        memory.write_8(0xC001,0x3E);
+        memory.write_8(0xC002,0x14);
+        memory.interrupts.write_ie(Interrupt::VBlank.bit());
+        memory.interrupts.request(Interrupt::VBlank);
+        cpu.cycle(&mut memory).unwrap();
+        assert!(!cpu.halted);
+        cpu.cycle(&mut memory).unwrap();
+        assert_eq!(cpu.registers.a,0x3E);
+        assert_eq!(cpu.registers.pc,0xC002);
+        cpu.cycle(&mut memory).unwrap();
+        assert_eq!(cpu.registers.d,0x01);
+    }
+    #[test]
+    fn test_stop_waits_for_joypad() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.pc = 0xC000;
+        memory.write_8(0xC000,0x10);
+        memory.write_8(0xC001,0x00);
+        memory.write_8(0xFF04,0xAB);
+        cpu.cycle(&mut memory).unwrap();
+        assert!(cpu.stopped);
+        assert_eq!(memory.read_8(0xFF04),0x00);
+        cpu.cycle(&mut memory).unwrap();
+        assert_eq!(cpu.registers.pc,0xC002);
+        memory.interrupts.request(Interrupt::Joypad);
+        cpu.cycle(&mut memory).unwrap();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.registers.pc,0xC003);
+    }
+    #[test]
+    fn test_stop_switches_speed_on_cgb() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        cpu.registers.pc = 0xC000;
+        memory.write_8(0xC000,0x10);
+        memory.write_8(0xFF4D,0x01);
+        cpu.cycle(&mut memory).unwrap();
+        assert!(!cpu.stopped);
+        assert!(memory.double_speed());
+        assert!(!memory.speed_switch_armed());
+    }
+    #[test]
+    fn test_illegal_opcode_policy() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.pc = 0xC000;
+        memory.write_8(0xC000,0xD3);
+        let error = cpu.cycle(&mut memory).unwrap_err();
+        assert_eq!(error, CpuError { pc: 0xC000, opcode: vec![0xD3], kind: CpuErrorKind::IllegalOpcode });
+        assert_eq!(cpu.registers.pc,0xC000);
+        cpu.illegal_opcode_policy = IllegalOpcodePolicy::Lock;
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),1);
+        //A locked CPU no longer services interrupts
+        cpu.ime = true;
+        memory.interrupts.write_ie(0x1F);
+        memory.interrupts.request(Interrupt::VBlank);
+        assert_eq!(cpu.cycle(&mut memory).unwrap(),1);
+        assert_eq!(cpu.registers.pc,0xC000);
+    }
+    #[test]
+    fn test_unsupported_operand() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        let result = cpu.execute(Instruction::INC8(LoadSource8::D8), &mut memory);
+        assert!(matches!(result, Err(CpuErrorKind::UnsupportedOperand(_))));
+    }
+    #[test]
+    fn test_unsupported_operand_leaves_registers() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.set_16(&Register16::HL,0xC000);
+        cpu.registers.pc = 0xC100;
+        //(HL+) is read and HL incremented before writing back to it fails
+        let result = cpu.execute_checked(Instruction::INC8(LoadSource8::AddressInc(Register16::HL)), &mut memory);
+        assert!(matches!(result, Err(CpuErrorKind::UnsupportedOperand(_))));
+        assert_eq!(cpu.registers.get_16(&Register16::HL),0xC000);
+        assert_eq!(cpu.registers.pc,0xC100);
+    }
+    #[test]
+    fn test_sub_flags() {
+        let mut cpu = CPU::new();
+        cpu.registers.a = 0x10;
+        let value = cpu.sub8(0x01, false);
+        assert_eq!(value,0x0F);
+        assert!(cpu.registers.f.subtract && cpu.registers.f.half_carry && !cpu.registers.f.carry);
+        cpu.registers.a = 0x00;
+        let value = cpu.sub8(0x00, true);
+        assert_eq!(value,0xFF);
+        assert!(cpu.registers.f.carry && cpu.registers.f.half_carry);
+    }
+    #[test]
+    fn test_daa() {
+        let mut cpu = CPU::new();
+        cpu.registers.a = 0x45;
+        cpu.registers.a = cpu.add8(0x38, false);
+        cpu.daa();
+        assert_eq!(cpu.registers.a,0x83);
+        assert!(!cpu.registers.f.carry);
+        cpu.registers.a = cpu.sub8(0x38, false);
+        cpu.daa();
+        assert_eq!(cpu.registers.a,0x45);
+    }
+    #[test]
+    fn test_add_sp() {
+        let mut cpu = CPU::new();
+        cpu.registers.sp = 0xFFF8;
+        assert_eq!(cpu.add_sp(0x08),0x0000);
+        assert!(cpu.registers.f.carry && cpu.registers.f.half_carry);
+        cpu.registers.sp = 0x0005;
+        assert_eq!(cpu.add_sp(-6),0xFFFF);
+        assert!(!cpu.registers.f.carry && !cpu.registers.f.half_carry);
+    }
+    #[test]
+    fn test_call_and_ret() {
+        let mut cpu = CPU::new();
+        let mut memory = Memory::new();
+        cpu.registers.sp = 0xFFFE;
+        cpu.registers.pc = 0xC000;
+        memory.write_8(0xC001,0x34);
+        memory.write_8(0xC002,0x12);
+        cpu.registers.pc = cpu.execute(Instruction::CALL(JumpCondition::Always), &mut memory).unwrap();
+        assert_eq!(cpu.registers.pc,0x1234);
+        cpu.registers.pc = cpu.execute(Instruction::RET(JumpCondition::Always), &mut memory).unwrap();
+        assert_eq!(cpu.registers.pc,0xC003);
+        assert_eq!(cpu.registers.sp,0xFFFE);
+    }
+}
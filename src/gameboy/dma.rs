@@ -0,0 +1,110 @@
+//Writing the upper byte of a source address to 0xFF46 copies 160 bytes from there to OAM
+pub const DMA_ADDRESS: u16 = 0xFF46;
+pub const OAM_SIZE: u16 = 0xA0;
+
+//The transfer starts one M-cycle after the write
+const STARTUP_DELAY: u8 = 1;
+
+//Which bus an address is reached through. A transfer occupies the bus it reads from, and OAM
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bus {
+    //Cartridge and work RAM
+    External,
+    Video,
+    Oam,
+    //IO registers and HRAM, never affected by DMA
+    Internal
+}
+
+impl Bus {
+    pub fn from_address(address: u16) -> Self {
+        match address {
+            0x8000..=0x9FFF => Bus::Video,
+            0xFE00..=0xFEFF => Bus::Oam,
+            0xFF00..=0xFFFF => Bus::Internal,
+            _ => Bus::External
+        }
+    }
+}
+
+//OAM DMA controller
+pub struct OamDma {
+    //Last value written to 0xFF46
+    register: u8,
+    source: u16,
+    //Bytes copied so far, the transfer is running while this is below OAM_SIZE
+    index: u16,
+    //M-cycles until a requested transfer starts, with its source
+    pending: Option<(u8, u16)>,
+    //Byte currently on the bus used by the transfer
+    last_byte: u8
+}
+
+impl OamDma {
+    pub fn new() -> Self {
+        Self {
+            register: 0xFF,
+            source: 0,
+            index: OAM_SIZE,
+            pending: None,
+            last_byte: 0xFF
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn set_register(&mut self, value: u8) {
+        self.register = value;
+    }
+
+    //Sources from 0xE000 up are read through echo RAM, so 0xFE00 and 0xFF00 end up in work RAM too
+    pub fn write(&mut self, value: u8) {
+        self.register = value;
+        let source = if value >= 0xE0 { (value - 0x20) as u16 } else { value as u16 } << 8;
+        self.pending = Some((STARTUP_DELAY, source));
+    }
+
+    pub fn active(&self) -> bool {
+        self.index < OAM_SIZE
+    }
+
+    //Advance by one M-cycle. Returns the (source, destination) pair to copy this cycle, if any
+    pub fn step(&mut self) -> Option<(u16, u16)> {
+        if let Some((delay, source)) = self.pending {
+            if delay == 0 {
+                //A restarted transfer takes over from the running one
+                self.pending = None;
+                self.source = source;
+                self.index = 0;
+            } else {
+                self.pending = Some((delay - 1, source));
+            }
+        }
+        if !self.active() {
+            return None;
+        }
+        let transfer = (self.source + self.index, self.index);
+        self.index += 1;
+        Some(transfer)
+    }
+
+    pub fn set_last_byte(&mut self, value: u8) {
+        self.last_byte = value;
+    }
+
+    //What a CPU read sees during a transfer, None if it goes through normally. OAM reads as 0xFF and
+    //the bus the transfer reads from returns the byte being copied
+    pub fn conflict(&self, address: u16) -> Option<u8> {
+        if !self.active() {
+            return None;
+        }
+        match Bus::from_address(address) {
+            Bus::Oam => Some(0xFF),
+            Bus::Internal => None,
+            bus if bus == Bus::from_address(self.source) => Some(self.last_byte),
+            _ => None
+        }
+    }
+}
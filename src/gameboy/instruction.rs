@@ -1,55 +1,114 @@
 //Instruction sets, in order of appearance during development
 #[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Instruction {
-    ADD8(Register8),
+    ADD8(LoadSource8),
     NOP,
     LD16(LoadSource16, LoadTarget16),
-    XOR8(Register8),
+    XOR8(LoadSource8),
     LD8(LoadSource8,LoadTarget8),
     BIT(LoadSource8,u8),
     JR(JumpCondition,LoadSource8),
-    INC8(Register8),
-    CALL(CallCondition),
+    INC8(LoadSource8),
+    CALL(JumpCondition),
     PUSH(Register16), // TODO: Verify that this should be a normal stack push, EG. Decrement SP, save upper byte, decrement again, save lower byte.
-    RET,
+    RET(JumpCondition),
     RL(LoadSource8),
     INC16(Register16),
     POP(Register16),
-    DEC8(Register8),
-    CP(LoadSource8)
-}
-
-#[derive(Debug)]
-pub enum CallCondition {
-    None
+    DEC8(LoadSource8),
+    CP(LoadSource8),
+    ADC8(LoadSource8),
+    SUB8(LoadSource8),
+    SBC8(LoadSource8),
+    AND8(LoadSource8),
+    OR8(LoadSource8),
+    DEC16(Register16),
+    ADD16(Register16), // ADD HL, r16
+    ADDSP, // ADD SP, s8
+    JP(JumpCondition,LoadSource16),
+    RST(u16),
+    RETI,
+    RLCA,
+    RRCA,
+    RLA,
+    RRA,
+    DAA,
+    CPL,
+    SCF,
+    CCF,
+    DI,
+    EI,
+    HALT,
+    STOP,
+    RLC(LoadSource8),
+    RRC(LoadSource8),
+    RR(LoadSource8),
+    SLA(LoadSource8),
+    SRA(LoadSource8),
+    SWAP(LoadSource8),
+    SRL(LoadSource8),
+    RES(LoadSource8,u8),
+    SET(LoadSource8,u8)
 }
 
 #[derive(Debug)]
 pub enum LoadSource8 {
-    Reg(Register8),Address(Register16),D8
+    Reg(Register8),Address(Register16),D8,AddressDec(Register16),AddressInc(Register16),OffsetAddress(Register8),OffsetA8,AddressA16
 }
 
 #[derive(Debug)]
 pub enum LoadTarget8 {
-    Reg(Register8), Address(Register16), AddressDec(Register16), AddressInc(Register16),OffsetAddress(Register8), OffsetA8, AddressD8
+    Reg(Register8), Address(Register16), AddressDec(Register16), AddressInc(Register16),OffsetAddress(Register8), OffsetA8, AddressA16
+}
+
+impl LoadSource8 {
+    //Extra machine cycles spent fetching the operand
+    fn cycles(&self) -> u8 {
+        match self {
+            LoadSource8::Reg(_) => 0,
+            LoadSource8::OffsetA8 => 2,
+            LoadSource8::AddressA16 => 3,
+            _ => 1
+        }
+    }
+}
+
+impl LoadTarget8 {
+    //Extra machine cycles spent storing to the target
+    fn cycles(&self) -> u8 {
+        match self {
+            LoadTarget8::Reg(_) => 0,
+            LoadTarget8::OffsetA8 => 2,
+            LoadTarget8::AddressA16 => 3,
+            _ => 1
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum LoadSource16 {
-    Reg(Register16),D16
+    Reg(Register16),D16,SPOffset // SP + s8
 }
 
 #[derive(Debug)]
 pub enum LoadTarget16 {
-    Reg(Register16)
+    Reg(Register16),AddressA16
 }
 
 #[derive(Debug)]
 pub enum JumpCondition {
-    NZ, Z
+    NZ, Z, NC, C, Always
+}
+
+impl JumpCondition {
+    fn taken(&self, branch_taken: bool) -> bool {
+        matches!(self, JumpCondition::Always) || branch_taken
+    }
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum Register8 {
     A,
     F,
@@ -62,6 +121,7 @@ pub enum Register8 {
 }
 
 #[derive(Debug)]
+#[allow(dead_code)]
 pub enum Register16 {
     AF,
     BC,
@@ -83,12 +143,121 @@ impl Instruction {
         }
     }
 
-    //Prefixed opcodes
+    //Number of bytes taken up by the instruction, counted from the byte the opcode was read from
+    pub fn size(&self) -> u16 {
+        match self {
+            Instruction::LD8(source, target) => {
+                match (source, target) {
+                    (LoadSource8::AddressA16, _) | (_, LoadTarget8::AddressA16) => 3,
+                    (LoadSource8::D8, _) | (LoadSource8::OffsetA8, _) | (_, LoadTarget8::OffsetA8) => 2,
+                    _ => 1
+                }
+            },
+            Instruction::LD16(source, target) => {
+                match (source, target) {
+                    (LoadSource16::D16, _) | (_, LoadTarget16::AddressA16) => 3,
+                    (LoadSource16::SPOffset, _) => 2,
+                    _ => 1
+                }
+            },
+            Instruction::ADD8(LoadSource8::D8) |
+            Instruction::ADC8(LoadSource8::D8) |
+            Instruction::SUB8(LoadSource8::D8) |
+            Instruction::SBC8(LoadSource8::D8) |
+            Instruction::AND8(LoadSource8::D8) |
+            Instruction::XOR8(LoadSource8::D8) |
+            Instruction::OR8(LoadSource8::D8) |
+            Instruction::CP(LoadSource8::D8) => 2,
+            Instruction::JR(_,_) | Instruction::ADDSP | Instruction::STOP => 2,
+            Instruction::JP(_, LoadSource16::D16) | Instruction::CALL(_) => 3,
+            _ => 1
+        }
+    }
+
+    //Number of machine cycles (M-cycles) the instruction takes, including the 0xCB prefix fetch.
+    //Conditional jumps, calls and returns take longer when the branch is taken.
+    pub fn cycles(&self, branch_taken: bool) -> u8 {
+        match self {
+            Instruction::LD8(source, target) => 1 + source.cycles() + target.cycles(),
+            Instruction::LD16(source, target) => {
+                match (source, target) {
+                    (_, LoadTarget16::AddressA16) => 5,
+                    (LoadSource16::D16, _) | (LoadSource16::SPOffset, _) => 3,
+                    _ => 2
+                }
+            },
+            Instruction::ADD8(source) |
+            Instruction::ADC8(source) |
+            Instruction::SUB8(source) |
+            Instruction::SBC8(source) |
+            Instruction::AND8(source) |
+            Instruction::XOR8(source) |
+            Instruction::OR8(source) |
+            Instruction::CP(source) => 1 + source.cycles(),
+            Instruction::INC8(source) | Instruction::DEC8(source) => 1 + 2 * source.cycles(),
+            Instruction::INC16(_) | Instruction::DEC16(_) | Instruction::ADD16(_) => 2,
+            Instruction::ADDSP => 4,
+            Instruction::JR(condition, _) => if condition.taken(branch_taken) { 3 } else { 2 },
+            Instruction::JP(_, LoadSource16::Reg(_)) => 1,
+            Instruction::JP(condition, _) => if condition.taken(branch_taken) { 4 } else { 3 },
+            Instruction::CALL(condition) => if condition.taken(branch_taken) { 6 } else { 3 },
+            Instruction::RET(JumpCondition::Always) => 4,
+            Instruction::RET(_) => if branch_taken { 5 } else { 2 },
+            Instruction::RETI | Instruction::RST(_) | Instruction::PUSH(_) => 4,
+            Instruction::POP(_) => 3,
+            Instruction::BIT(source, _) => 2 + source.cycles(),
+            Instruction::RLC(source) |
+            Instruction::RRC(source) |
+            Instruction::RL(source) |
+            Instruction::RR(source) |
+            Instruction::SLA(source) |
+            Instruction::SRA(source) |
+            Instruction::SWAP(source) |
+            Instruction::SRL(source) |
+            Instruction::RES(source, _) |
+            Instruction::SET(source, _) => 2 + 2 * source.cycles(),
+            _ => 1
+        }
+    }
+
+    //Prefixed opcodes. The lower 3 bits select the operand, bits 3-5 select the shift type or bit index
     fn decode_prefixed(byte: u8) -> Option<Instruction> {
+        let operand = Instruction::decode_operand(byte);
+        let index = (byte >> 3) & 0x07;
         match byte {
-            0x7C => Some(Instruction::BIT(LoadSource8::Reg(Register8::H),7)), // BIT 7 H
-            0x11 => Some(Instruction::RL(LoadSource8::Reg(Register8::C))), // RL C
-            _ => None
+            0x00..=0x07 => Some(Instruction::RLC(operand)), // RLC r
+            0x08..=0x0F => Some(Instruction::RRC(operand)), // RRC r
+            0x10..=0x17 => Some(Instruction::RL(operand)), // RL r
+            0x18..=0x1F => Some(Instruction::RR(operand)), // RR r
+            0x20..=0x27 => Some(Instruction::SLA(operand)), // SLA r
+            0x28..=0x2F => Some(Instruction::SRA(operand)), // SRA r
+            0x30..=0x37 => Some(Instruction::SWAP(operand)), // SWAP r
+            0x38..=0x3F => Some(Instruction::SRL(operand)), // SRL r
+            0x40..=0x7F => Some(Instruction::BIT(operand,index)), // BIT n r
+            0x80..=0xBF => Some(Instruction::RES(operand,index)), // RES n r
+            0xC0..=0xFF => Some(Instruction::SET(operand,index)) // SET n r
+        }
+    }
+
+    //Operand encoded in the lower 3 bits of register/register opcodes: B, C, D, E, H, L, (HL), A
+    fn decode_operand(bits: u8) -> LoadSource8 {
+        match bits & 0x07 {
+            0 => LoadSource8::Reg(Register8::B),
+            1 => LoadSource8::Reg(Register8::C),
+            2 => LoadSource8::Reg(Register8::D),
+            3 => LoadSource8::Reg(Register8::E),
+            4 => LoadSource8::Reg(Register8::H),
+            5 => LoadSource8::Reg(Register8::L),
+            6 => LoadSource8::Address(Register16::HL),
+            _ => LoadSource8::Reg(Register8::A)
+        }
+    }
+
+    //Same encoding as decode_operand, used as the destination of LD r r
+    fn decode_target(bits: u8) -> LoadTarget8 {
+        match Instruction::decode_operand(bits) {
+            LoadSource8::Reg(register) => LoadTarget8::Reg(register),
+            _ => LoadTarget8::Address(Register16::HL)
         }
     }
 
@@ -96,38 +265,228 @@ impl Instruction {
     fn decode_not_prefixed(byte: u8) -> Option<Instruction> {
         match byte {
             0x00 => Some(Instruction::NOP), //NOP
-            0x31 => Some(Instruction::LD16(LoadSource16::D16,LoadTarget16::Reg(Register16::SP))), //LD SP D16
-            0xAF => Some(Instruction::XOR8(Register8::A)), //XOR A
-            0x21 => Some(Instruction::LD16(LoadSource16::D16, LoadTarget16::Reg(Register16::HL))), // LD HL D16
-            0x32 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::AddressDec(Register16::HL))), // LD (HL) A
-            0x20 => Some(Instruction::JR(JumpCondition::NZ,LoadSource8::D8)), // JR NZ D8
+            0x01 => Some(Instruction::LD16(LoadSource16::D16,LoadTarget16::Reg(Register16::BC))), // LD BC d16
+            0x02 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::Address(Register16::BC))), // LD (BC) A
+            0x03 => Some(Instruction::INC16(Register16::BC)), // INC BC
+            0x04 => Some(Instruction::INC8(LoadSource8::Reg(Register8::B))), // INC B
+            0x05 => Some(Instruction::DEC8(LoadSource8::Reg(Register8::B))), // DEC B
+            0x06 => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::B))), // LD B d8
+            0x07 => Some(Instruction::RLCA), // RLCA
+            0x08 => Some(Instruction::LD16(LoadSource16::Reg(Register16::SP),LoadTarget16::AddressA16)), // LD (a16) SP
+            0x09 => Some(Instruction::ADD16(Register16::BC)), // ADD HL BC
+            0x0A => Some(Instruction::LD8(LoadSource8::Address(Register16::BC),LoadTarget8::Reg(Register8::A))), // LD A (BC)
+            0x0B => Some(Instruction::DEC16(Register16::BC)), // DEC BC
+            0x0C => Some(Instruction::INC8(LoadSource8::Reg(Register8::C))), // INC C
+            0x0D => Some(Instruction::DEC8(LoadSource8::Reg(Register8::C))), // DEC C
             0x0E => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::C))), //LD C D8
-            0x3E => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::A))), //LD A D8
-            0xE2 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::OffsetAddress(Register8::C))), // LD (C) A
-            0x0C => Some(Instruction::INC8(Register8::C)), // INC C
-            0x77 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::Address(Register16::HL))), // LD (HL) A
-            0xe0 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::OffsetA8)), // LD (a8) A
+            0x0F => Some(Instruction::RRCA), // RRCA
+
+            0x10 => Some(Instruction::STOP), // STOP
             0x11 => Some(Instruction::LD16(LoadSource16::D16,LoadTarget16::Reg(Register16::DE))), // LD DE d16
+            0x12 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::Address(Register16::DE))), // LD (DE) A
+            0x13 => Some(Instruction::INC16(Register16::DE)), // INC DE
+            0x14 => Some(Instruction::INC8(LoadSource8::Reg(Register8::D))), // INC D
+            0x15 => Some(Instruction::DEC8(LoadSource8::Reg(Register8::D))), // DEC D
+            0x16 => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::D))), // LD D d8
+            0x17 => Some(Instruction::RLA), // RLA
+            0x18 => Some(Instruction::JR(JumpCondition::Always,LoadSource8::D8)), // JR s8
+            0x19 => Some(Instruction::ADD16(Register16::DE)), // ADD HL DE
             0x1A => Some(Instruction::LD8(LoadSource8::Address(Register16::DE),LoadTarget8::Reg(Register8::A))), // LD A (DE)
-            0xCD => Some(Instruction::CALL(CallCondition::None)), // Call a16
-            0x4F => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::Reg(Register8::C))), // LD C A
-            0x06 => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::B))), // LD B d8
-            0xc5 => Some(Instruction::PUSH(Register16::BC)), // PUSH BC
-            0x17 => Some(Instruction::RL(LoadSource8::Reg(Register8::A))), // RL A
+            0x1B => Some(Instruction::DEC16(Register16::DE)), // DEC DE
+            0x1C => Some(Instruction::INC8(LoadSource8::Reg(Register8::E))), // INC E
+            0x1D => Some(Instruction::DEC8(LoadSource8::Reg(Register8::E))), // DEC E
+            0x1E => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::E))), // LD E d8
+            0x1F => Some(Instruction::RRA), // RRA
+
+            0x20 => Some(Instruction::JR(JumpCondition::NZ,LoadSource8::D8)), // JR NZ D8
+            0x21 => Some(Instruction::LD16(LoadSource16::D16, LoadTarget16::Reg(Register16::HL))), // LD HL D16
             0x22 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::AddressInc(Register16::HL))), // LD (HL+) A
-            0xC1 => Some(Instruction::POP(Register16::BC)), // POP BC
-            0x05 => Some(Instruction::DEC8(Register8::B)), // DEC B
             0x23 => Some(Instruction::INC16(Register16::HL)), // INC HL
-            0xC9 => Some(Instruction::RET), // RET
-            0x13 => Some(Instruction::INC16(Register16::DE)), // INC DE
-            0x7B => Some(Instruction::LD8(LoadSource8::Reg(Register8::E),LoadTarget8::Reg(Register8::A))), // LD A E
-            0xFE => Some(Instruction::CP(LoadSource8::D8)), // CP d8
-            0xEA => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::AddressD8)), // LD (a16) A
-            0x3D => Some(Instruction::DEC8(Register8::A)), // DEC A
+            0x24 => Some(Instruction::INC8(LoadSource8::Reg(Register8::H))), // INC H
+            0x25 => Some(Instruction::DEC8(LoadSource8::Reg(Register8::H))), // DEC H
+            0x26 => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::H))), // LD H d8
+            0x27 => Some(Instruction::DAA), // DAA
             0x28 => Some(Instruction::JR(JumpCondition::Z,LoadSource8::D8)), // JR Z s8
-            0x67 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::Reg(Register8::H))), // LD H A
-            0x57 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::Reg(Register8::D))), // LD D A
+            0x29 => Some(Instruction::ADD16(Register16::HL)), // ADD HL HL
+            0x2A => Some(Instruction::LD8(LoadSource8::AddressInc(Register16::HL),LoadTarget8::Reg(Register8::A))), // LD A (HL+)
+            0x2B => Some(Instruction::DEC16(Register16::HL)), // DEC HL
+            0x2C => Some(Instruction::INC8(LoadSource8::Reg(Register8::L))), // INC L
+            0x2D => Some(Instruction::DEC8(LoadSource8::Reg(Register8::L))), // DEC L
+            0x2E => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::L))), // LD L d8
+            0x2F => Some(Instruction::CPL), // CPL
+
+            0x30 => Some(Instruction::JR(JumpCondition::NC,LoadSource8::D8)), // JR NC s8
+            0x31 => Some(Instruction::LD16(LoadSource16::D16,LoadTarget16::Reg(Register16::SP))), //LD SP D16
+            0x32 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::AddressDec(Register16::HL))), // LD (HL-) A
+            0x33 => Some(Instruction::INC16(Register16::SP)), // INC SP
+            0x34 => Some(Instruction::INC8(LoadSource8::Address(Register16::HL))), // INC (HL)
+            0x35 => Some(Instruction::DEC8(LoadSource8::Address(Register16::HL))), // DEC (HL)
+            0x36 => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Address(Register16::HL))), // LD (HL) d8
+            0x37 => Some(Instruction::SCF), // SCF
+            0x38 => Some(Instruction::JR(JumpCondition::C,LoadSource8::D8)), // JR C s8
+            0x39 => Some(Instruction::ADD16(Register16::SP)), // ADD HL SP
+            0x3A => Some(Instruction::LD8(LoadSource8::AddressDec(Register16::HL),LoadTarget8::Reg(Register8::A))), // LD A (HL-)
+            0x3B => Some(Instruction::DEC16(Register16::SP)), // DEC SP
+            0x3C => Some(Instruction::INC8(LoadSource8::Reg(Register8::A))), // INC A
+            0x3D => Some(Instruction::DEC8(LoadSource8::Reg(Register8::A))), // DEC A
+            0x3E => Some(Instruction::LD8(LoadSource8::D8,LoadTarget8::Reg(Register8::A))), //LD A D8
+            0x3F => Some(Instruction::CCF), // CCF
+
+            0x76 => Some(Instruction::HALT), // HALT
+            0x40..=0x7F => Some(Instruction::LD8(Instruction::decode_operand(byte),Instruction::decode_target(byte >> 3))), // LD r r
+
+            0x80..=0x87 => Some(Instruction::ADD8(Instruction::decode_operand(byte))), // ADD A r
+            0x88..=0x8F => Some(Instruction::ADC8(Instruction::decode_operand(byte))), // ADC A r
+            0x90..=0x97 => Some(Instruction::SUB8(Instruction::decode_operand(byte))), // SUB A r
+            0x98..=0x9F => Some(Instruction::SBC8(Instruction::decode_operand(byte))), // SBC A r
+            0xA0..=0xA7 => Some(Instruction::AND8(Instruction::decode_operand(byte))), // AND A r
+            0xA8..=0xAF => Some(Instruction::XOR8(Instruction::decode_operand(byte))), // XOR A r
+            0xB0..=0xB7 => Some(Instruction::OR8(Instruction::decode_operand(byte))), // OR A r
+            0xB8..=0xBF => Some(Instruction::CP(Instruction::decode_operand(byte))), // CP A r
+
+            0xC0 => Some(Instruction::RET(JumpCondition::NZ)), // RET NZ
+            0xC1 => Some(Instruction::POP(Register16::BC)), // POP BC
+            0xC2 => Some(Instruction::JP(JumpCondition::NZ,LoadSource16::D16)), // JP NZ a16
+            0xC3 => Some(Instruction::JP(JumpCondition::Always,LoadSource16::D16)), // JP a16
+            0xC4 => Some(Instruction::CALL(JumpCondition::NZ)), // CALL NZ a16
+            0xC5 => Some(Instruction::PUSH(Register16::BC)), // PUSH BC
+            0xC6 => Some(Instruction::ADD8(LoadSource8::D8)), // ADD A d8
+            0xC7 => Some(Instruction::RST(0x00)), // RST 00
+            0xC8 => Some(Instruction::RET(JumpCondition::Z)), // RET Z
+            0xC9 => Some(Instruction::RET(JumpCondition::Always)), // RET
+            0xCA => Some(Instruction::JP(JumpCondition::Z,LoadSource16::D16)), // JP Z a16
+            0xCC => Some(Instruction::CALL(JumpCondition::Z)), // CALL Z a16
+            0xCD => Some(Instruction::CALL(JumpCondition::Always)), // Call a16
+            0xCE => Some(Instruction::ADC8(LoadSource8::D8)), // ADC A d8
+            0xCF => Some(Instruction::RST(0x08)), // RST 08
+
+            0xD0 => Some(Instruction::RET(JumpCondition::NC)), // RET NC
+            0xD1 => Some(Instruction::POP(Register16::DE)), // POP DE
+            0xD2 => Some(Instruction::JP(JumpCondition::NC,LoadSource16::D16)), // JP NC a16
+            0xD4 => Some(Instruction::CALL(JumpCondition::NC)), // CALL NC a16
+            0xD5 => Some(Instruction::PUSH(Register16::DE)), // PUSH DE
+            0xD6 => Some(Instruction::SUB8(LoadSource8::D8)), // SUB d8
+            0xD7 => Some(Instruction::RST(0x10)), // RST 10
+            0xD8 => Some(Instruction::RET(JumpCondition::C)), // RET C
+            0xD9 => Some(Instruction::RETI), // RETI
+            0xDA => Some(Instruction::JP(JumpCondition::C,LoadSource16::D16)), // JP C a16
+            0xDC => Some(Instruction::CALL(JumpCondition::C)), // CALL C a16
+            0xDE => Some(Instruction::SBC8(LoadSource8::D8)), // SBC A d8
+            0xDF => Some(Instruction::RST(0x18)), // RST 18
+
+            0xE0 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::OffsetA8)), // LD (a8) A
+            0xE1 => Some(Instruction::POP(Register16::HL)), // POP HL
+            0xE2 => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::OffsetAddress(Register8::C))), // LD (C) A
+            0xE5 => Some(Instruction::PUSH(Register16::HL)), // PUSH HL
+            0xE6 => Some(Instruction::AND8(LoadSource8::D8)), // AND d8
+            0xE7 => Some(Instruction::RST(0x20)), // RST 20
+            0xE8 => Some(Instruction::ADDSP), // ADD SP s8
+            0xE9 => Some(Instruction::JP(JumpCondition::Always,LoadSource16::Reg(Register16::HL))), // JP HL
+            0xEA => Some(Instruction::LD8(LoadSource8::Reg(Register8::A),LoadTarget8::AddressA16)), // LD (a16) A
+            0xEE => Some(Instruction::XOR8(LoadSource8::D8)), // XOR d8
+            0xEF => Some(Instruction::RST(0x28)), // RST 28
+
+            0xF0 => Some(Instruction::LD8(LoadSource8::OffsetA8,LoadTarget8::Reg(Register8::A))), // LD A (a8)
+            0xF1 => Some(Instruction::POP(Register16::AF)), // POP AF
+            0xF2 => Some(Instruction::LD8(LoadSource8::OffsetAddress(Register8::C),LoadTarget8::Reg(Register8::A))), // LD A (C)
+            0xF3 => Some(Instruction::DI), // DI
+            0xF5 => Some(Instruction::PUSH(Register16::AF)), // PUSH AF
+            0xF6 => Some(Instruction::OR8(LoadSource8::D8)), // OR d8
+            0xF7 => Some(Instruction::RST(0x30)), // RST 30
+            0xF8 => Some(Instruction::LD16(LoadSource16::SPOffset,LoadTarget16::Reg(Register16::HL))), // LD HL SP+s8
+            0xF9 => Some(Instruction::LD16(LoadSource16::Reg(Register16::HL),LoadTarget16::Reg(Register16::SP))), // LD SP HL
+            0xFA => Some(Instruction::LD8(LoadSource8::AddressA16,LoadTarget8::Reg(Register8::A))), // LD A (a16)
+            0xFB => Some(Instruction::EI), // EI
+            0xFE => Some(Instruction::CP(LoadSource8::D8)), // CP d8
+            0xFF => Some(Instruction::RST(0x38)), // RST 38
             _ => None
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn decodes_all_legal_opcodes() {
+        let illegal = [0xCB, 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD];
+        for byte in 0..=0xFFu8 {
+            let decoded = Instruction::decode(byte, false);
+            assert_eq!(decoded.is_none(), illegal.contains(&byte), "opcode {:#04x}", byte);
+        }
+    }
+    #[test]
+    fn decodes_prefixed_opcodes() {
+        for byte in 0..=0xFFu8 {
+            assert!(Instruction::decode(byte, true).is_some(), "opcode 0xCB{:02x}", byte);
+        }
+        match Instruction::decode(0x7C, true) {
+            Some(Instruction::BIT(LoadSource8::Reg(Register8::H),7)) => {},
+            other => panic!("0xCB7C decoded as {:?}", other)
+        }
+        match Instruction::decode(0xC6, true) {
+            Some(Instruction::SET(LoadSource8::Address(Register16::HL),0)) => {},
+            other => panic!("0xCBC6 decoded as {:?}", other)
+        }
+        match Instruction::decode(0x37, true) {
+            Some(Instruction::SWAP(LoadSource8::Reg(Register8::A))) => {},
+            other => panic!("0xCB37 decoded as {:?}", other)
+        }
+    }
+    #[test]
+    fn unprefixed_cycles() {
+        //Not taken timings for conditional branches, 0 for illegal opcodes
+        let expected: [u8; 256] = [
+            1,3,2,2,1,1,2,1,5,2,2,2,1,1,2,1,
+            1,3,2,2,1,1,2,1,3,2,2,2,1,1,2,1,
+            2,3,2,2,1,1,2,1,2,2,2,2,1,1,2,1,
+            2,3,2,2,3,3,3,1,2,2,2,2,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            2,2,2,2,2,2,1,2,1,1,1,1,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            1,1,1,1,1,1,2,1,1,1,1,1,1,1,2,1,
+            2,3,3,4,3,4,2,4,2,4,3,0,3,6,2,4,
+            2,3,3,0,3,4,2,4,2,4,3,0,3,0,2,4,
+            3,3,2,0,0,4,2,4,4,1,4,0,0,0,2,4,
+            3,3,2,1,0,4,2,4,3,2,4,1,0,0,2,4
+        ];
+        for byte in 0..=0xFFu8 {
+            let cycles = Instruction::decode(byte, false).map_or(0, |instruction| instruction.cycles(false));
+            assert_eq!(cycles, expected[byte as usize], "opcode {:#04x}", byte);
+        }
+    }
+    #[test]
+    fn branch_taken_cycles() {
+        assert_eq!(Instruction::decode(0x20, false).unwrap().cycles(true), 3);
+        assert_eq!(Instruction::decode(0xC2, false).unwrap().cycles(true), 4);
+        assert_eq!(Instruction::decode(0xC4, false).unwrap().cycles(true), 6);
+        assert_eq!(Instruction::decode(0xC0, false).unwrap().cycles(true), 5);
+    }
+    #[test]
+    fn prefixed_cycles() {
+        for byte in 0..=0xFFu8 {
+            let expected = match (byte & 0x07, byte) {
+                (6, 0x40..=0x7F) => 3,
+                (6, _) => 4,
+                _ => 2
+            };
+            assert_eq!(Instruction::decode(byte, true).unwrap().cycles(false), expected, "opcode 0xCB{:02x}", byte);
+        }
+    }
+    #[test]
+    fn instruction_sizes() {
+        assert_eq!(Instruction::decode(0x00, false).unwrap().size(), 1);
+        assert_eq!(Instruction::decode(0x3E, false).unwrap().size(), 2);
+        assert_eq!(Instruction::decode(0xE0, false).unwrap().size(), 2);
+        assert_eq!(Instruction::decode(0xF0, false).unwrap().size(), 2);
+        assert_eq!(Instruction::decode(0xEA, false).unwrap().size(), 3);
+        assert_eq!(Instruction::decode(0x08, false).unwrap().size(), 3);
+        assert_eq!(Instruction::decode(0xC3, false).unwrap().size(), 3);
+        assert_eq!(Instruction::decode(0xE9, false).unwrap().size(), 1);
+        assert_eq!(Instruction::decode(0xF8, false).unwrap().size(), 2);
+    }
+}
@@ -0,0 +1,118 @@
+//Interrupt sources in priority order, VBlank being the highest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    VBlank,
+    LcdStat,
+    Timer,
+    Serial,
+    Joypad
+}
+
+const INTERRUPTS: [Interrupt; 5] = [Interrupt::VBlank, Interrupt::LcdStat, Interrupt::Timer, Interrupt::Serial, Interrupt::Joypad];
+
+//Only the lower 5 bits of IF are backed by hardware, the rest always read as 1
+const IF_UNUSED_BITS: u8 = 0xE0;
+
+impl Interrupt {
+    //Bit position in the IE and IF registers
+    pub fn bit(&self) -> u8 {
+        match self {
+            Interrupt::VBlank => 0x01,
+            Interrupt::LcdStat => 0x02,
+            Interrupt::Timer => 0x04,
+            Interrupt::Serial => 0x08,
+            Interrupt::Joypad => 0x10
+        }
+    }
+
+    //Address the CPU jumps to when dispatching the interrupt
+    pub fn vector(&self) -> u16 {
+        match self {
+            Interrupt::VBlank => 0x40,
+            Interrupt::LcdStat => 0x48,
+            Interrupt::Timer => 0x50,
+            Interrupt::Serial => 0x58,
+            Interrupt::Joypad => 0x60
+        }
+    }
+}
+
+//IE (0xFFFF) and IF (0xFF0F) registers
+#[derive(Default)]
+pub struct InterruptController {
+    enabled: u8,
+    flags: u8
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self {
+            enabled: 0,
+            flags: 0
+        }
+    }
+
+    pub fn read_ie(&self) -> u8 {
+        self.enabled
+    }
+
+    pub fn write_ie(&mut self, value: u8) {
+        self.enabled = value;
+    }
+
+    pub fn read_if(&self) -> u8 {
+        self.flags | IF_UNUSED_BITS
+    }
+
+    pub fn write_if(&mut self, value: u8) {
+        self.flags = value & !IF_UNUSED_BITS;
+    }
+
+    pub fn request(&mut self, interrupt: Interrupt) {
+        self.flags |= interrupt.bit();
+    }
+
+    //Clear the request flag once the CPU has started servicing the interrupt
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.flags &= !interrupt.bit();
+    }
+
+    pub fn is_requested(&self, interrupt: Interrupt) -> bool {
+        self.flags & interrupt.bit() != 0
+    }
+
+    //Whether any requested interrupt is also enabled, regardless of IME
+    pub fn is_pending(&self) -> bool {
+        self.enabled & self.flags & !IF_UNUSED_BITS != 0
+    }
+
+    //Highest priority interrupt that is both requested and enabled
+    pub fn highest_pending(&self) -> Option<Interrupt> {
+        let pending = self.enabled & self.flags;
+        INTERRUPTS.iter().copied().find(|interrupt| pending & interrupt.bit() != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn priority_order() {
+        let mut interrupts = InterruptController::new();
+        interrupts.write_ie(0x1F);
+        interrupts.request(Interrupt::Joypad);
+        interrupts.request(Interrupt::Timer);
+        assert_eq!(interrupts.highest_pending(),Some(Interrupt::Timer));
+        interrupts.acknowledge(Interrupt::Timer);
+        assert_eq!(interrupts.highest_pending(),Some(Interrupt::Joypad));
+    }
+    #[test]
+    fn disabled_interrupts_are_not_pending() {
+        let mut interrupts = InterruptController::new();
+        interrupts.write_ie(Interrupt::VBlank.bit());
+        interrupts.request(Interrupt::Serial);
+        assert!(!interrupts.is_pending());
+        assert_eq!(interrupts.highest_pending(),None);
+        assert_eq!(interrupts.read_if(),0xE8);
+    }
+}
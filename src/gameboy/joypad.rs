@@ -0,0 +1,135 @@
+use super::interrupts::{Interrupt, InterruptController};
+
+//Bits 4 and 5 of P1 select the direction pad and the action buttons, low meaning selected
+const SELECT_BITS: u8 = 0x30;
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_ACTIONS: u8 = 0x20;
+const UNUSED_BITS: u8 = 0xC0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start
+}
+
+impl Button {
+    //Input line pulled low while the button is pressed, and whether it is in the action row
+    fn line(&self) -> (u8, bool) {
+        match self {
+            Button::Right => (0x01, false),
+            Button::Left => (0x02, false),
+            Button::Up => (0x04, false),
+            Button::Down => (0x08, false),
+            Button::A => (0x01, true),
+            Button::B => (0x02, true),
+            Button::Select => (0x04, true),
+            Button::Start => (0x08, true)
+        }
+    }
+}
+
+//P1/JOYP register at 0xFF00
+pub struct Joypad {
+    select: u8,
+    //Pressed buttons as a mask of lines, one per row
+    directions: u8,
+    actions: u8
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Self {
+            select: SELECT_BITS,
+            directions: 0,
+            actions: 0
+        }
+    }
+
+    //Lower 4 bits as the program sees them. Lines read as 1 unless a button in a selected row holds them low
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            pressed |= self.directions;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            pressed |= self.actions;
+        }
+        !pressed & 0x0F
+    }
+
+    //The interrupt is requested when any line goes from high to low
+    fn update(&mut self, previous_lines: u8, interrupts: &mut InterruptController) {
+        if previous_lines & !self.lines() != 0 {
+            interrupts.request(Interrupt::Joypad);
+        }
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool, interrupts: &mut InterruptController) {
+        let previous_lines = self.lines();
+        let (line, action) = button.line();
+        let row = if action { &mut self.actions } else { &mut self.directions };
+        if pressed {
+            *row |= line;
+        } else {
+            *row &= !line;
+        }
+        self.update(previous_lines, interrupts);
+    }
+
+    pub fn read(&self) -> u8 {
+        UNUSED_BITS | self.select | self.lines()
+    }
+
+    pub fn set_register(&mut self, value: u8) {
+        self.select = value & SELECT_BITS;
+    }
+
+    //Selecting a row with a held button also pulls its line low
+    pub fn write(&mut self, value: u8, interrupts: &mut InterruptController) {
+        let previous_lines = self.lines();
+        self.select = value & SELECT_BITS;
+        self.update(previous_lines, interrupts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn row_selection() {
+        let mut joypad = Joypad::new();
+        let mut interrupts = InterruptController::new();
+        joypad.set_button(Button::Start, true, &mut interrupts);
+        joypad.set_button(Button::Left, true, &mut interrupts);
+        assert_eq!(joypad.read(),0xFF);
+        joypad.write(0x20, &mut interrupts);
+        assert_eq!(joypad.read(),0xED);
+        joypad.write(0x10, &mut interrupts);
+        assert_eq!(joypad.read(),0xD7);
+        joypad.write(0x00, &mut interrupts);
+        assert_eq!(joypad.read(),0xC5);
+    }
+
+    #[test]
+    fn interrupt_on_high_to_low() {
+        let mut joypad = Joypad::new();
+        let mut interrupts = InterruptController::new();
+        //Nothing selected, so no line moves
+        joypad.set_button(Button::A, true, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Joypad));
+        joypad.write(0x10, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Joypad));
+        interrupts.acknowledge(Interrupt::Joypad);
+        joypad.set_button(Button::A, false, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Joypad));
+        joypad.set_button(Button::B, true, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Joypad));
+    }
+}
@@ -0,0 +1,111 @@
+use super::cartridge::{CartridgeError, CartridgeHeader, MbcKind, ROM_BANK_SIZE, RAM_BANK_SIZE};
+
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod rtc;
+
+pub use mbc1::Mbc1;
+pub use mbc2::Mbc2;
+pub use mbc3::Mbc3;
+pub use mbc5::Mbc5;
+pub use rtc::RtcClock;
+
+//Cartridge hardware sitting between the bus and the ROM and RAM chips.
+//Writes to the ROM area are register writes. Addresses are absolute bus addresses, 0x0000-0x7FFF and 0xA000-0xBFFF.
+pub trait MemoryBankController {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8;
+    fn write_rom(&mut self, address: u16, value: u8);
+    fn read_ram(&self, address: u16) -> u8;
+    fn write_ram(&mut self, address: u16, value: u8);
+
+    //Whole external RAM, for battery backed saves
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    //Advance any cartridge clock by the given number of T-cycles
+    fn tick(&mut self, _cycles: u64) {}
+
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+
+    //Real time clock state in the footer format appended to save files, if the cartridge has one
+    fn save_rtc(&mut self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_rtc(&mut self, _data: &[u8]) {}
+
+    //Whether the rumble motor is currently switched on
+    fn rumble(&self) -> bool {
+        false
+    }
+}
+
+//Build the controller for the cartridge type in the header
+pub fn new_mbc(header: &CartridgeHeader, rom: &[u8]) -> Result<Box<dyn MemoryBankController>, CartridgeError> {
+    let ram_size = header.ram_size()?;
+    match header.cartridge_type.mbc() {
+        MbcKind::RomOnly => Ok(Box::new(RomOnly::new(ram_size))),
+        MbcKind::Mbc1 => Ok(Box::new(Mbc1::new(rom, ram_size))),
+        //MBC2 RAM is inside the controller, the header declares none
+        MbcKind::Mbc2 => Ok(Box::new(Mbc2::new())),
+        MbcKind::Mbc3 => Ok(Box::new(Mbc3::new(ram_size, header.cartridge_type.has_timer()))),
+        MbcKind::Mbc5 => Ok(Box::new(Mbc5::new(ram_size, header.cartridge_type.has_rumble()))),
+        kind => Err(CartridgeError::UnsupportedMbc(kind))
+    }
+}
+
+//Offset into the ROM of a byte in the given bank, wrapping around ROMs smaller than the bank number
+fn rom_offset(rom: &[u8], bank: usize, address: u16) -> usize {
+    let bank_count = (rom.len() / ROM_BANK_SIZE).max(1);
+    (bank % bank_count) * ROM_BANK_SIZE + (address as usize & (ROM_BANK_SIZE - 1))
+}
+
+//Offset into RAM of a byte in the given bank, wrapping around RAM smaller than the bank number
+fn ram_offset(ram: &[u8], bank: usize, address: u16) -> usize {
+    (bank * RAM_BANK_SIZE + (address as usize & (RAM_BANK_SIZE - 1))) % ram.len()
+}
+
+//32 KiB cartridges without a controller, optionally with up to 8 KiB of RAM
+pub struct RomOnly {
+    ram: Vec<u8>
+}
+
+impl RomOnly {
+    pub fn new(ram_size: usize) -> Self {
+        Self {
+            ram: vec![0; ram_size]
+        }
+    }
+}
+
+impl MemoryBankController for RomOnly {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        rom.get(address as usize).copied().unwrap_or(0xFF)
+    }
+
+    fn write_rom(&mut self, _address: u16, _value: u8) {}
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, 0, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, 0, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
@@ -0,0 +1,209 @@
+use super::{MemoryBankController, rom_offset, ram_offset};
+use super::super::cartridge::ROM_BANK_SIZE;
+
+const RAM_ENABLE_LOCATION: (u16, u16) = (0x0000, 0x1FFF);
+const ROM_BANK_LOCATION: (u16, u16) = (0x2000, 0x3FFF);
+const RAM_BANK_LOCATION: (u16, u16) = (0x4000, 0x5FFF);
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+
+//Multicarts are 1 MiB and repeat the Nintendo logo at the start of every 256 KiB game
+const MULTICART_SIZE: usize = 0x100000;
+const MULTICART_GAME_BANKS: usize = 0x10;
+const LOGO_LOCATION: (usize, usize) = (0x0104, 0x0133);
+
+pub struct Mbc1 {
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    //5 bit register at 0x2000-0x3FFF
+    rom_bank: u8,
+    //2 bit register at 0x4000-0x5FFF, either the RAM bank or the upper ROM bank bits
+    upper_bank: u8,
+    //Mode 1 also applies the upper register to 0x0000-0x3FFF and RAM
+    advanced_banking: bool,
+    //MBC1M wires the upper register to ROM bank bit 4 instead of bit 5
+    multicart: bool
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8], ram_size: usize) -> Self {
+        Self {
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            upper_bank: 0,
+            advanced_banking: false,
+            multicart: Mbc1::is_multicart(rom)
+        }
+    }
+
+    //There is no header flag for MBC1M, so look for a second game's logo in bank 0x10
+    fn is_multicart(rom: &[u8]) -> bool {
+        if rom.len() != MULTICART_SIZE {
+            return false;
+        }
+        let logo = &rom[LOGO_LOCATION.0..=LOGO_LOCATION.1];
+        let second_game = MULTICART_GAME_BANKS * ROM_BANK_SIZE;
+        &rom[second_game + LOGO_LOCATION.0..=second_game + LOGO_LOCATION.1] == logo
+    }
+
+    fn upper_bank_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    //Bank visible at 0x0000-0x3FFF
+    fn zero_bank(&self) -> usize {
+        if self.advanced_banking {
+            (self.upper_bank << self.upper_bank_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    //Bank visible at 0x4000-0x7FFF. The zero check happens on all 5 bits even on MBC1M, where
+    //only the lower 4 are connected
+    fn high_bank(&self) -> usize {
+        let lower = if self.multicart { self.rom_bank & 0x0F } else { self.rom_bank };
+        ((self.upper_bank << self.upper_bank_shift()) | lower) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking {
+            self.upper_bank as usize
+        } else {
+            0
+        }
+    }
+}
+
+impl MemoryBankController for Mbc1 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if (address as usize) < ROM_BANK_SIZE { self.zero_bank() } else { self.high_bank() };
+        rom[rom_offset(rom, bank, address)]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            _ if address <= RAM_ENABLE_LOCATION.1 => self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE,
+            _ if address <= ROM_BANK_LOCATION.1 => {
+                //Bank 0 can not be selected here, writing 0 selects bank 1
+                self.rom_bank = match value & 0x1F {
+                    0 => 1,
+                    bank => bank
+                };
+            },
+            _ if address <= RAM_BANK_LOCATION.1 => self.upper_bank = value & 0x03,
+            _ => self.advanced_banking = value & 0x01 != 0
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank(), address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank(), address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //ROM where the first byte of every bank holds the bank number
+    fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    #[test]
+    fn rom_bank_switching() {
+        let rom = banked_rom(128);
+        let mut mbc = Mbc1::new(&rom, 0);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),1);
+        mbc.write_rom(0x2000,0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),5);
+        mbc.write_rom(0x4000,0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x25);
+        assert_eq!(mbc.read_rom(&rom, 0x0000),0);
+    }
+
+    #[test]
+    fn bank_zero_quirk() {
+        let rom = banked_rom(128);
+        let mut mbc = Mbc1::new(&rom, 0);
+        mbc.write_rom(0x2000,0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),1);
+        //Bank 0x20 can not be reached through 0x4000-0x7FFF
+        mbc.write_rom(0x4000,0x01);
+        mbc.write_rom(0x2000,0x20);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x21);
+        //But mode 1 maps it to 0x0000-0x3FFF
+        mbc.write_rom(0x6000,0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000),0x20);
+    }
+
+    #[test]
+    fn bank_number_wraps_to_rom_size() {
+        let rom = banked_rom(4);
+        let mut mbc = Mbc1::new(&rom, 0);
+        mbc.write_rom(0x2000,0x07);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),3);
+    }
+
+    #[test]
+    fn ram_enable_and_banking() {
+        let rom = banked_rom(4);
+        let mut mbc = Mbc1::new(&rom, 0x8000);
+        mbc.write_ram(0xA000,0x12);
+        assert_eq!(mbc.read_ram(0xA000),0xFF);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_ram(0xA000,0x12);
+        assert_eq!(mbc.read_ram(0xA000),0x12);
+        //RAM banking only applies in mode 1
+        mbc.write_rom(0x4000,0x02);
+        assert_eq!(mbc.read_ram(0xA000),0x12);
+        mbc.write_rom(0x6000,0x01);
+        assert_eq!(mbc.read_ram(0xA000),0x00);
+        mbc.write_ram(0xA000,0x34);
+        mbc.write_rom(0x6000,0x00);
+        assert_eq!(mbc.read_ram(0xA000),0x12);
+        mbc.write_rom(0x0000,0x00);
+        assert_eq!(mbc.read_ram(0xA000),0xFF);
+    }
+
+    #[test]
+    fn multicart_wiring() {
+        let mut rom = banked_rom(64);
+        for game in 0..4 {
+            let start = game * MULTICART_GAME_BANKS * ROM_BANK_SIZE;
+            rom[start + LOGO_LOCATION.0..=start + LOGO_LOCATION.1].fill(0xCE);
+        }
+        let mut mbc = Mbc1::new(&rom, 0);
+        assert!(mbc.multicart);
+        mbc.write_rom(0x4000,0x01);
+        mbc.write_rom(0x2000,0x12);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x12);
+        mbc.write_rom(0x6000,0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000),0x10);
+        //The zero check still uses bit 4, so 0x10 selects bank 0 of the game
+        mbc.write_rom(0x2000,0x10);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x10);
+    }
+}
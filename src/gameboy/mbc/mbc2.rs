@@ -0,0 +1,106 @@
+use super::{MemoryBankController, rom_offset};
+use super::super::cartridge::ROM_BANK_SIZE;
+
+const REGISTER_LOCATION: (u16, u16) = (0x0000, 0x3FFF);
+//Address bit 8 chooses between the RAM enable and ROM bank registers
+const REGISTER_SELECT_BIT: u16 = 0x0100;
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+
+//512 half bytes built into the controller, mirrored across 0xA000-0xBFFF
+const RAM_SIZE: usize = 0x200;
+
+pub struct Mbc2 {
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    //4 bit register, selected by writes with address bit 8 set
+    rom_bank: u8
+}
+
+impl Mbc2 {
+    pub fn new() -> Self {
+        Self {
+            ram: vec![0; RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1
+        }
+    }
+}
+
+impl MemoryBankController for Mbc2 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if (address as usize) < ROM_BANK_SIZE { 0 } else { self.rom_bank as usize };
+        rom[rom_offset(rom, bank, address)]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        if address > REGISTER_LOCATION.1 {
+            return;
+        }
+        if address & REGISTER_SELECT_BIT == 0 {
+            self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE;
+        } else {
+            self.rom_bank = match value & 0x0F {
+                0 => 1,
+                bank => bank
+            };
+        }
+    }
+
+    //Only the lower 4 bits exist, the upper ones read as set
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        self.ram[address as usize % RAM_SIZE] | 0xF0
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled {
+            self.ram[address as usize % RAM_SIZE] = value & 0x0F;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_select_by_address_bit_8() {
+        let mut rom = vec![0; 16 * ROM_BANK_SIZE];
+        for bank in 0..16 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc = Mbc2::new();
+        //Bit 8 clear writes the RAM enable register, so the bank does not change
+        mbc.write_rom(0x2000,0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),1);
+        mbc.write_rom(0x2100,0x05);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),5);
+        mbc.write_rom(0x0100,0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),1);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_ram(0x0000,0x0C);
+        assert_eq!(mbc.read_ram(0x0000),0xFC);
+    }
+
+    #[test]
+    fn half_byte_ram_is_mirrored() {
+        let mut mbc = Mbc2::new();
+        mbc.write_ram(0x0000,0x05);
+        assert_eq!(mbc.read_ram(0x0000),0xFF);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_ram(0x0010,0xAB);
+        assert_eq!(mbc.read_ram(0x0010),0xFB);
+        assert_eq!(mbc.read_ram(0x0210),0xFB);
+        assert_eq!(mbc.read_ram(0x1E10),0xFB);
+    }
+}
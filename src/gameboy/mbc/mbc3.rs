@@ -0,0 +1,174 @@
+use super::{MemoryBankController, rom_offset, ram_offset};
+use super::rtc::{Rtc, RtcClock, RTC_SECONDS, RTC_DAY_HIGH};
+use super::super::cartridge::ROM_BANK_SIZE;
+
+const RAM_ENABLE_LOCATION: (u16, u16) = (0x0000, 0x1FFF);
+const ROM_BANK_LOCATION: (u16, u16) = (0x2000, 0x3FFF);
+const RAM_BANK_LOCATION: (u16, u16) = (0x4000, 0x5FFF);
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+
+pub struct Mbc3 {
+    ram: Vec<u8>,
+    //Enables both RAM and the RTC registers
+    ram_enabled: bool,
+    //7 bit register at 0x2000-0x3FFF
+    rom_bank: u8,
+    //0x00-0x07 select a RAM bank, 0x08-0x0C an RTC register
+    ram_bank: u8,
+    rtc: Option<Rtc>
+}
+
+impl Mbc3 {
+    pub fn new(ram_size: usize, has_timer: bool) -> Self {
+        Self {
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: if has_timer { Some(Rtc::new()) } else { None }
+        }
+    }
+
+    //RTC register selected in place of a RAM bank, if the cartridge has a clock
+    fn selected_rtc_register(&self) -> Option<u8> {
+        match self.ram_bank {
+            RTC_SECONDS..=RTC_DAY_HIGH if self.rtc.is_some() => Some(self.ram_bank),
+            _ => None
+        }
+    }
+}
+
+impl MemoryBankController for Mbc3 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if (address as usize) < ROM_BANK_SIZE { 0 } else { self.rom_bank as usize };
+        rom[rom_offset(rom, bank, address)]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            _ if address <= RAM_ENABLE_LOCATION.1 => self.ram_enabled = value & 0x0F == RAM_ENABLE_VALUE,
+            _ if address <= ROM_BANK_LOCATION.1 => {
+                self.rom_bank = match value & 0x7F {
+                    0 => 1,
+                    bank => bank
+                };
+            },
+            _ if address <= RAM_BANK_LOCATION.1 => self.ram_bank = value & 0x0F,
+            _ => {
+                if let Some(ref mut rtc) = self.rtc {
+                    rtc.write_latch(value);
+                }
+            }
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled {
+            return 0xFF;
+        }
+        match (self.selected_rtc_register(), &self.rtc) {
+            (Some(register), Some(rtc)) => rtc.read(register),
+            _ if self.ram.is_empty() || self.ram_bank > 0x07 => 0xFF,
+            _ => self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+        }
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match (self.selected_rtc_register(), &mut self.rtc) {
+            (Some(register), Some(rtc)) => rtc.write(register, value),
+            _ if self.ram.is_empty() || self.ram_bank > 0x07 => {},
+            _ => {
+                let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+                self.ram[offset] = value;
+            }
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn tick(&mut self, cycles: u64) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.clock = clock;
+        }
+    }
+
+    fn save_rtc(&mut self) -> Option<Vec<u8>> {
+        self.rtc.as_mut().map(|rtc| rtc.save())
+    }
+
+    fn load_rtc(&mut self, data: &[u8]) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.load(data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::CLOCK_SPEED;
+
+    #[test]
+    fn rom_bank_switching() {
+        let mut rom = vec![0; 128 * ROM_BANK_SIZE];
+        for bank in 0..128 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        let mut mbc = Mbc3::new(0, false);
+        mbc.write_rom(0x2000,0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),1);
+        mbc.write_rom(0x2000,0x7F);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x7F);
+        //Unlike MBC1, 0x20 is reachable
+        mbc.write_rom(0x2000,0x20);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x20);
+        assert_eq!(mbc.read_rom(&rom, 0x0000),0);
+    }
+
+    #[test]
+    fn ram_banks() {
+        let mut mbc = Mbc3::new(0x8000, false);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_rom(0x4000,0x03);
+        mbc.write_ram(0xA000,0x33);
+        mbc.write_rom(0x4000,0x00);
+        assert_eq!(mbc.read_ram(0xA000),0x00);
+        mbc.write_rom(0x4000,0x03);
+        assert_eq!(mbc.read_ram(0xA000),0x33);
+        //RTC registers read as open bus when there is no clock
+        mbc.write_rom(0x4000,0x08);
+        assert_eq!(mbc.read_ram(0xA000),0xFF);
+    }
+
+    #[test]
+    fn rtc_registers() {
+        let mut mbc = Mbc3::new(0x8000, true);
+        mbc.set_rtc_clock(RtcClock::Emulated);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_rom(0x4000,0x09);
+        mbc.write_ram(0xA000,10);
+        mbc.tick(CLOCK_SPEED * 61);
+        mbc.write_rom(0x6000,0x00);
+        mbc.write_rom(0x6000,0x01);
+        assert_eq!(mbc.read_ram(0xA000),11);
+        mbc.write_rom(0x4000,0x08);
+        assert_eq!(mbc.read_ram(0xA000),1);
+        mbc.write_rom(0x0000,0x00);
+        assert_eq!(mbc.read_ram(0xA000),0xFF);
+    }
+}
@@ -0,0 +1,134 @@
+use super::{MemoryBankController, rom_offset, ram_offset};
+use super::super::cartridge::ROM_BANK_SIZE;
+
+const RAM_ENABLE_LOCATION: (u16, u16) = (0x0000, 0x1FFF);
+const ROM_BANK_LOW_LOCATION: (u16, u16) = (0x2000, 0x2FFF);
+const ROM_BANK_HIGH_LOCATION: (u16, u16) = (0x3000, 0x3FFF);
+const RAM_BANK_LOCATION: (u16, u16) = (0x4000, 0x5FFF);
+const RAM_ENABLE_VALUE: u8 = 0x0A;
+
+//On rumble cartridges bit 3 of the RAM bank register drives the motor instead of selecting a bank
+const RUMBLE_BIT: u8 = 0x08;
+
+pub struct Mbc5 {
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    //9 bit register split over 0x2000-0x2FFF and 0x3000-0x3FFF, bank 0 can be selected
+    rom_bank: u16,
+    //4 bit register at 0x4000-0x5FFF, 3 bit on rumble cartridges
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool
+}
+
+impl Mbc5 {
+    pub fn new(ram_size: usize, has_rumble: bool) -> Self {
+        Self {
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false
+        }
+    }
+}
+
+impl MemoryBankController for Mbc5 {
+    fn read_rom(&self, rom: &[u8], address: u16) -> u8 {
+        let bank = if (address as usize) < ROM_BANK_SIZE { 0 } else { self.rom_bank as usize };
+        rom[rom_offset(rom, bank, address)]
+    }
+
+    fn write_rom(&mut self, address: u16, value: u8) {
+        match address {
+            //Unlike the older controllers, all 8 bits are compared
+            _ if address <= RAM_ENABLE_LOCATION.1 => self.ram_enabled = value == RAM_ENABLE_VALUE,
+            _ if address <= ROM_BANK_LOW_LOCATION.1 => self.rom_bank = (self.rom_bank & 0x100) | value as u16,
+            _ if address <= ROM_BANK_HIGH_LOCATION.1 => {
+                self.rom_bank = (self.rom_bank & 0xFF) | ((value & 0x01) as u16) << 8;
+            },
+            _ if address <= RAM_BANK_LOCATION.1 => {
+                if self.has_rumble {
+                    self.rumble = value & RUMBLE_BIT != 0;
+                    self.ram_bank = value & 0x07;
+                } else {
+                    self.ram_bank = value & 0x0F;
+                }
+            },
+            _ => {}
+        }
+    }
+
+    fn read_ram(&self, address: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return 0xFF;
+        }
+        self.ram[ram_offset(&self.ram, self.ram_bank as usize, address)]
+    }
+
+    fn write_ram(&mut self, address: u16, value: u8) {
+        if self.ram_enabled && !self.ram.is_empty() {
+            let offset = ram_offset(&self.ram, self.ram_bank as usize, address);
+            self.ram[offset] = value;
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nine_bit_rom_bank() {
+        let mut rom = vec![0; 512 * ROM_BANK_SIZE];
+        for bank in 0..512 {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        let mut mbc = Mbc5::new(0, false);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),1);
+        //Bank 0 is reachable through 0x4000-0x7FFF
+        mbc.write_rom(0x2000,0x00);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0);
+        mbc.write_rom(0x2000,0x23);
+        mbc.write_rom(0x3000,0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x4000),0x23);
+        assert_eq!(mbc.read_rom(&rom, 0x4001),0x01);
+        assert_eq!(mbc.read_rom(&rom, 0x0000),0);
+    }
+
+    #[test]
+    fn ram_banks_and_rumble() {
+        let mut mbc = Mbc5::new(16 * 0x2000, false);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_rom(0x4000,0x0F);
+        mbc.write_ram(0xA000,0x5A);
+        mbc.write_rom(0x4000,0x07);
+        assert_eq!(mbc.read_ram(0xA000),0x00);
+        mbc.write_rom(0x4000,0x0F);
+        assert_eq!(mbc.read_ram(0xA000),0x5A);
+        assert!(!mbc.rumble());
+
+        let mut mbc = Mbc5::new(4 * 0x2000, true);
+        mbc.write_rom(0x0000,0x0A);
+        mbc.write_rom(0x4000,0x09);
+        assert!(mbc.rumble());
+        mbc.write_ram(0xA000,0x11);
+        mbc.write_rom(0x4000,0x01);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read_ram(0xA000),0x11);
+    }
+}
@@ -0,0 +1,255 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::CLOCK_SPEED;
+
+//Register numbers selected by writing 0x08-0x0C to 0x4000-0x5FFF: seconds, minutes, hours,
+//lower 8 bits of the day counter, and the day counter's top bit with the halt and carry flags
+pub const RTC_SECONDS: u8 = 0x08;
+pub const RTC_DAY_HIGH: u8 = 0x0C;
+
+const DAY_HIGH_BIT: u8 = 0x01;
+const HALT_BIT: u8 = 0x40;
+const DAY_CARRY_BIT: u8 = 0x80;
+const MAX_DAYS: u64 = 512;
+
+//Size of the RTC footer appended to save files: 10 little endian u32 registers and a u64 timestamp
+pub const RTC_SAVE_SIZE: usize = 48;
+
+//What drives the real time clock forward
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RtcClock {
+    //Follow the host's wall clock, also while the emulator is not running
+    #[default]
+    WallClock,
+    //Advance one second for every CLOCK_SPEED emulated T-cycles, for deterministic runs
+    Emulated
+}
+
+pub struct Rtc {
+    pub clock: RtcClock,
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    //Copy of the registers taken by the latch, which is what the program reads
+    latched: [u8; 5],
+    //Latching happens on a write of 0x01 following a write of 0x00
+    latch_armed: bool,
+    //T-cycles towards the next second when emulated, or the last time the clock was brought up to date
+    subsecond_cycles: u64,
+    last_update: u64
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Self {
+            clock: RtcClock::default(),
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            latched: [0; 5],
+            latch_armed: false,
+            subsecond_cycles: 0,
+            last_update: unix_time()
+        }
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            (self.days & 0xFF) as u8,
+            (self.days >> 8) as u8 & DAY_HIGH_BIT
+                | if self.halted { HALT_BIT } else { 0 }
+                | if self.day_carry { DAY_CARRY_BIT } else { 0 }
+        ]
+    }
+
+    fn set_registers(&mut self, registers: [u8; 5]) {
+        self.seconds = registers[0] & 0x3F;
+        self.minutes = registers[1] & 0x3F;
+        self.hours = registers[2] & 0x1F;
+        self.days = registers[3] as u16 | ((registers[4] & DAY_HIGH_BIT) as u16) << 8;
+        self.halted = registers[4] & HALT_BIT != 0;
+        self.day_carry = registers[4] & DAY_CARRY_BIT != 0;
+    }
+
+    //Move the clock forward, setting the day carry when the 9 bit day counter overflows
+    fn advance(&mut self, seconds: u64) {
+        if self.halted || seconds == 0 {
+            return;
+        }
+        let total_seconds = self.seconds as u64 + seconds;
+        let total_minutes = self.minutes as u64 + total_seconds / 60;
+        let total_hours = self.hours as u64 + total_minutes / 60;
+        let total_days = self.days as u64 + total_hours / 24;
+        self.seconds = (total_seconds % 60) as u8;
+        self.minutes = (total_minutes % 60) as u8;
+        self.hours = (total_hours % 24) as u8;
+        self.days = (total_days % MAX_DAYS) as u16;
+        if total_days >= MAX_DAYS {
+            self.day_carry = true;
+        }
+    }
+
+    //Catch up with the host clock
+    fn update(&mut self) {
+        if self.clock == RtcClock::WallClock {
+            let now = unix_time();
+            self.advance(now.saturating_sub(self.last_update));
+            self.last_update = now;
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        if self.clock == RtcClock::Emulated && !self.halted {
+            self.subsecond_cycles += cycles;
+            let seconds = self.subsecond_cycles / CLOCK_SPEED;
+            self.subsecond_cycles %= CLOCK_SPEED;
+            self.advance(seconds);
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        if self.latch_armed && value == 0x01 {
+            self.update();
+            self.latched = self.registers();
+        }
+        self.latch_armed = value == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        self.latched[(register - RTC_SECONDS) as usize]
+    }
+
+    pub fn write(&mut self, register: u8, value: u8) {
+        self.update();
+        let mut registers = self.registers();
+        registers[(register - RTC_SECONDS) as usize] = value;
+        self.set_registers(registers);
+        //Writing the seconds register resets the divider counting towards the next second
+        if register == RTC_SECONDS {
+            self.subsecond_cycles = 0;
+        }
+    }
+
+    //Footer format shared with other emulators: current registers, latched registers, then a unix timestamp
+    pub fn save(&mut self) -> Vec<u8> {
+        self.update();
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+        for register in self.registers().iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(*register as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&unix_time().to_le_bytes());
+        data
+    }
+
+    //Restore a saved clock. When following the wall clock, the time spent switched off is added on
+    pub fn load(&mut self, data: &[u8]) {
+        if data.len() < RTC_SAVE_SIZE {
+            return;
+        }
+        let register = |index: usize| data[index * 4];
+        self.set_registers([register(0), register(1), register(2), register(3), register(4)]);
+        self.latched = [register(5), register(6), register(7), register(8), register(9)];
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&data[40..48]);
+        self.last_update = u64::from_le_bytes(timestamp);
+        self.update();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RTC_MINUTES: u8 = 0x09;
+    const RTC_HOURS: u8 = 0x0A;
+    const RTC_DAY_LOW: u8 = 0x0B;
+
+    fn latch(rtc: &mut Rtc) {
+        rtc.write_latch(0x00);
+        rtc.write_latch(0x01);
+    }
+
+    #[test]
+    fn emulated_clock_advances_with_cycles() {
+        let mut rtc = Rtc::new();
+        rtc.clock = RtcClock::Emulated;
+        rtc.write(RTC_SECONDS, 59);
+        rtc.write(RTC_MINUTES, 59);
+        rtc.write(RTC_HOURS, 23);
+        rtc.write(RTC_DAY_LOW, 0xFF);
+        rtc.write(RTC_DAY_HIGH, 0x01);
+        rtc.tick(CLOCK_SPEED - 1);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(RTC_SECONDS), 59);
+        rtc.tick(1);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(RTC_SECONDS), 0);
+        assert_eq!(rtc.read(RTC_MINUTES), 0);
+        assert_eq!(rtc.read(RTC_HOURS), 0);
+        assert_eq!(rtc.read(RTC_DAY_LOW), 0);
+        assert_eq!(rtc.read(RTC_DAY_HIGH), DAY_CARRY_BIT);
+    }
+
+    #[test]
+    fn latch_requires_zero_then_one() {
+        let mut rtc = Rtc::new();
+        rtc.clock = RtcClock::Emulated;
+        rtc.tick(CLOCK_SPEED * 5);
+        rtc.write_latch(0x01);
+        assert_eq!(rtc.read(RTC_SECONDS), 0);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(RTC_SECONDS), 5);
+        //The latched value does not move until the next latch
+        rtc.tick(CLOCK_SPEED);
+        assert_eq!(rtc.read(RTC_SECONDS), 5);
+    }
+
+    #[test]
+    fn halt_stops_the_clock() {
+        let mut rtc = Rtc::new();
+        rtc.clock = RtcClock::Emulated;
+        rtc.write(RTC_DAY_HIGH, HALT_BIT);
+        rtc.tick(CLOCK_SPEED * 10);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(RTC_SECONDS), 0);
+        assert_eq!(rtc.read(RTC_DAY_HIGH), HALT_BIT);
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut rtc = Rtc::new();
+        rtc.clock = RtcClock::Emulated;
+        rtc.write(RTC_MINUTES, 42);
+        latch(&mut rtc);
+        let data = rtc.save();
+        assert_eq!(data.len(), RTC_SAVE_SIZE);
+        let mut restored = Rtc::new();
+        restored.clock = RtcClock::Emulated;
+        restored.load(&data);
+        assert_eq!(restored.read(RTC_MINUTES), 42);
+    }
+
+    #[test]
+    fn wall_clock_catches_up_on_load() {
+        let mut rtc = Rtc::new();
+        let mut data = rtc.save();
+        let an_hour_ago = unix_time() - 3600;
+        data[40..48].copy_from_slice(&an_hour_ago.to_le_bytes());
+        rtc.load(&data);
+        latch(&mut rtc);
+        assert_eq!(rtc.read(RTC_HOURS), 1);
+    }
+}
@@ -1,17 +1,57 @@
-const MEMORY_SIZE: usize = 0xFFFF;
+const MEMORY_SIZE: usize = 0x10000;
 const BOOT_LOCATION: usize = 0;
 const ROM_BANK_0_LOCATION: (usize,usize) = (0x0000,0x3FFF);
+//Switchable ROM bank, mapped by the cartridge's memory bank controller
 const ROM_BANK_1_LOCATION: (usize,usize) = (0x4000,0x7FFF);
 const VRAM_LOCATION: (usize, usize) = (0x8000,0x9FFF);
 const EXTERNAL_RAM_LOCATION: (usize,usize) = (0xA000,0xBFFF);
 const WRAM1_LOCATION:(usize,usize) = (0xC000,0xCFFF);
 const WRAM2_LOCATION:(usize,usize) = (0xD000,0xDFFF);
-const ECHO_RAM_LOCATION:(usize,usize) = (0xC000,0xDDFF);
+const ECHO_RAM_LOCATION:(usize,usize) = (0xE000,0xFDFF);
 const SPRITE_TABLE_LOCATION:(usize,usize) = (0xFE00,0xFE9F);
 const NOT_USABLE_LOCATION:(usize,usize) = (0xFEA0,0xFEFF);
 const IO_REGISTERS_LOCATION:(usize,usize) = (0xFF00,0xFF7F);
 const HRAM_LOCATION:(usize,usize) = (0xFF80,0xFFFE);
 const IE_LOCATION:(usize,usize) = (0xFFFF,0xFFFF); 
+const JOYPAD_ADDRESS: u16 = 0xFF00;
+const SERIAL_LOCATION: (u16, u16) = (0xFF01, 0xFF02);
+const TIMER_LOCATION: (u16, u16) = (0xFF04, 0xFF07);
+const DIV_ADDRESS: u16 = 0xFF04;
+const IF_ADDRESS: u16 = 0xFF0F;
+const APU_LOCATION: (u16, u16) = (0xFF10, 0xFF3F);
+const LCD_LOCATION: (u16, u16) = (0xFF40, 0xFF4B);
+const KEY1_ADDRESS: usize = 0xFF4D;
+const BOOT_ROM_DISABLE_ADDRESS: u16 = 0xFF50;
+//CGB only registers kept as plain storage, with the bits that always read as 1
+const CGB_IO_REGISTERS: [(u16, u8); 15] = [
+    (0xFF4D, 0x7E), // KEY1
+    (0xFF4F, 0xFE), // VBK
+    (0xFF51, 0x00), // HDMA1
+    (0xFF52, 0x00), // HDMA2
+    (0xFF53, 0x00), // HDMA3
+    (0xFF54, 0x00), // HDMA4
+    (0xFF55, 0x00), // HDMA5
+    (0xFF56, 0x3C), // RP
+    (0xFF68, 0x40), // BCPS
+    (0xFF69, 0x00), // BCPD
+    (0xFF6A, 0x40), // OCPS
+    (0xFF6B, 0x00), // OCPD
+    (0xFF6C, 0xFE), // OPRI
+    (0xFF70, 0xF8), // SVBK
+    (0xFF72, 0x00) // Undocumented
+];
+//The CGB boot ROM is larger than 256 bytes and leaves a hole for the cartridge header
+const CGB_BOOT_ROM_HOLE:(usize,usize) = (0x0100,0x01FF);
+
+use super::interrupts::InterruptController;
+use super::cartridge::Cartridge;
+use super::model::Model;
+use super::joypad::Joypad;
+use super::serial::Serial;
+use super::timer::Timer;
+use super::apu::Apu;
+use super::screen::Screen;
+use super::dma::{OamDma, DMA_ADDRESS};
 
 pub enum MemoryLocation {
     RomBank0,
@@ -28,36 +68,521 @@ pub enum MemoryLocation {
     InterruptEnableRegister
 }
 
+impl MemoryLocation {
+    pub fn from_address(address: u16) -> Self {
+        let address = address as usize;
+        match address {
+            _ if address <= ROM_BANK_0_LOCATION.1 => MemoryLocation::RomBank0,
+            _ if address <= ROM_BANK_1_LOCATION.1 => MemoryLocation::RomBank1,
+            _ if address <= VRAM_LOCATION.1 => MemoryLocation::VideoRAM,
+            _ if address <= EXTERNAL_RAM_LOCATION.1 => MemoryLocation::ExternalRAM,
+            _ if address <= WRAM1_LOCATION.1 => MemoryLocation::WorkRAM1,
+            _ if address <= WRAM2_LOCATION.1 => MemoryLocation::WorkRAM2,
+            _ if address <= ECHO_RAM_LOCATION.1 => MemoryLocation::EchoRam,
+            _ if address <= SPRITE_TABLE_LOCATION.1 => MemoryLocation::SpriteTable,
+            _ if address <= NOT_USABLE_LOCATION.1 => MemoryLocation::NotUsable,
+            _ if address <= IO_REGISTERS_LOCATION.1 => MemoryLocation::IORegisters,
+            _ if address <= HRAM_LOCATION.1 => MemoryLocation::HighRam,
+            _ => {
+                debug_assert_eq!(address, IE_LOCATION.0);
+                MemoryLocation::InterruptEnableRegister
+            }
+        }
+    }
+}
+
 pub struct Memory {
-    bytes: [u8; MEMORY_SIZE]
+    bytes: [u8; MEMORY_SIZE],
+    pub interrupts: InterruptController,
+    pub joypad: Joypad,
+    pub serial: Serial,
+    pub timer: Timer,
+    pub apu: Apu,
+    pub screen: Screen,
+    dma: OamDma,
+    pub model: Model,
+    cartridge: Option<Cartridge>,
+    //Boot ROM shadowing the start of the cartridge until a write to 0xFF50
+    boot_rom: Vec<u8>,
+    boot_rom_mapped: bool
 }
 
 impl Memory {
     pub fn new() -> Self {
+        let model = Model::default();
         Self {
-            bytes: [0;MEMORY_SIZE]
+            bytes: [0;MEMORY_SIZE],
+            interrupts: InterruptController::new(),
+            joypad: Joypad::new(),
+            serial: Serial::new(model.is_cgb()),
+            timer: Timer::new(),
+            apu: Apu::new(),
+            screen: Screen::new(),
+            dma: OamDma::new(),
+            model,
+            cartridge: None,
+            boot_rom: Vec::new(),
+            boot_rom_mapped: false
         }
     }
 
+    //Components with model specific registers are rebuilt for the new model
+    pub fn set_model(&mut self, model: Model) {
+        self.model = model;
+        self.serial = Serial::new(model.is_cgb());
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: Cartridge) {
+        self.cartridge = Some(cartridge);
+    }
+
+    pub fn cartridge(&self) -> Option<&Cartridge> {
+        self.cartridge.as_ref()
+    }
 
+    pub fn cartridge_mut(&mut self) -> Option<&mut Cartridge> {
+        self.cartridge.as_mut()
+    }
 
-    pub fn load_boot_rom(&mut self,data: & Vec<u8>) {
-        for (i,elem) in data.iter().enumerate() {
-            self.bytes[BOOT_LOCATION+i] = *elem;
+    //Advance the components clocked by the CPU, and the PPU which runs at the same dot rate in either speed
+    pub fn tick(&mut self, m_cycles: u64, dots: u64) {
+        self.timer.tick(m_cycles, &mut self.interrupts);
+        self.serial.tick(m_cycles, &mut self.interrupts);
+        for _ in 0..m_cycles {
+            self.step_dma();
         }
+        self.screen.tick(dots, &mut self.interrupts);
     }
 
-    pub fn read_8(&self, address: u16) -> u8{
-        self.bytes[address as usize] 
+    //Copy one byte of a running OAM DMA transfer
+    fn step_dma(&mut self) {
+        if let Some((source, index)) = self.dma.step() {
+            let value = self.read_bus(source);
+            self.screen.write_oam(index as usize, value);
+            self.dma.set_last_byte(value);
+        }
+    }
+
+    pub fn reset_div(&mut self) {
+        self.timer.write(DIV_ADDRESS, 0);
+    }
+
+    //KEY1 bit 0 is set by the program to request a speed switch on the next STOP
+    pub fn speed_switch_armed(&self) -> bool {
+        self.model.is_cgb() && self.bytes[KEY1_ADDRESS] & 0x01 != 0
+    }
+
+    //Toggle the current speed in KEY1 bit 7 and clear the switch request
+    pub fn switch_speed(&mut self) {
+        self.bytes[KEY1_ADDRESS] = (self.bytes[KEY1_ADDRESS] ^ 0x80) & 0x80;
+    }
+
+    pub fn double_speed(&self) -> bool {
+        self.model.is_cgb() && self.bytes[KEY1_ADDRESS] & 0x80 != 0
+    }
+
+    pub fn load_boot_rom(&mut self,data: &[u8]) {
+        self.boot_rom = data.to_vec();
+        self.boot_rom_mapped = true;
+    }
+
+    //Hand over to the cartridge without running the boot ROM
+    pub fn unmap_boot_rom(&mut self) {
+        self.boot_rom_mapped = false;
+    }
+
+    //Set a hardware register to its post boot value, storing it in the owning component without any of
+    //the side effects of a bus write: no transfers, timer glitches, LCD enable logic or interrupts.
+    //Registers without side effects (IF, KEY1, VBK, SVBK) take the normal write path
+    pub fn set_io_register(&mut self, address: u16, value: u8) {
+        match address {
+            _ if address as usize == IE_LOCATION.0 => self.interrupts.write_ie(value),
+            JOYPAD_ADDRESS => self.joypad.set_register(value),
+            DMA_ADDRESS => self.dma.set_register(value),
+            _ if (SERIAL_LOCATION.0..=SERIAL_LOCATION.1).contains(&address) => self.serial.set_register(address, value),
+            _ if (TIMER_LOCATION.0..=TIMER_LOCATION.1).contains(&address) => self.timer.set_register(address, value),
+            _ if (APU_LOCATION.0..=APU_LOCATION.1).contains(&address) => self.apu.set_register(address, value),
+            _ if (LCD_LOCATION.0..=LCD_LOCATION.1).contains(&address) => self.screen.set_register(address, value),
+            _ => self.write_io(address, value)
+        }
+    }
+
+    fn cgb_io_register(&self, address: u16) -> Option<u8> {
+        if !self.model.is_cgb() {
+            return None;
+        }
+        CGB_IO_REGISTERS.iter().find(|(register, _)| *register == address).map(|(_, unused_bits)| *unused_bits)
+    }
+
+    //Route 0xFF00-0xFF7F to the component owning the register. Unmapped addresses read as 0xFF
+    fn read_io(&self, address: u16) -> u8 {
+        match address {
+            JOYPAD_ADDRESS => self.joypad.read(),
+            DMA_ADDRESS => self.dma.read(),
+            _ if (SERIAL_LOCATION.0..=SERIAL_LOCATION.1).contains(&address) => self.serial.read(address),
+            _ if (TIMER_LOCATION.0..=TIMER_LOCATION.1).contains(&address) => self.timer.read(address),
+            IF_ADDRESS => self.interrupts.read_if(),
+            _ if (APU_LOCATION.0..=APU_LOCATION.1).contains(&address) => self.apu.read(address),
+            _ if (LCD_LOCATION.0..=LCD_LOCATION.1).contains(&address) => self.screen.read(address),
+            BOOT_ROM_DISABLE_ADDRESS => 0xFF,
+            _ => match self.cgb_io_register(address) {
+                Some(unused_bits) => self.bytes[address as usize] | unused_bits,
+                None => 0xFF
+            }
+        }
+    }
+
+    fn write_io(&mut self, address: u16, value: u8) {
+        match address {
+            JOYPAD_ADDRESS => self.joypad.write(value, &mut self.interrupts),
+            DMA_ADDRESS => self.dma.write(value),
+            _ if (SERIAL_LOCATION.0..=SERIAL_LOCATION.1).contains(&address) => self.serial.write(address, value),
+            _ if (TIMER_LOCATION.0..=TIMER_LOCATION.1).contains(&address) => self.timer.write(address, value),
+            IF_ADDRESS => self.interrupts.write_if(value),
+            _ if (APU_LOCATION.0..=APU_LOCATION.1).contains(&address) => self.apu.write(address, value),
+            _ if (LCD_LOCATION.0..=LCD_LOCATION.1).contains(&address) => self.screen.write(address, value, &mut self.interrupts),
+            //Unmapping the boot ROM is permanent until the next power cycle
+            BOOT_ROM_DISABLE_ADDRESS => {
+                if value != 0 {
+                    self.boot_rom_mapped = false;
+                }
+            },
+            //Only the switch request is writable, the current speed in bit 7 only changes on STOP
+            _ if address as usize == KEY1_ADDRESS && self.model.is_cgb() => {
+                self.bytes[KEY1_ADDRESS] = (self.bytes[KEY1_ADDRESS] & 0x80) | (value & 0x01);
+            },
+            _ => {
+                if let Some(unused_bits) = self.cgb_io_register(address) {
+                    self.bytes[address as usize] = value & !unused_bits;
+                }
+            }
+        }
+    }
+
+    fn read_boot_rom(&self, address: u16) -> Option<u8> {
+        let address = address as usize;
+        if !self.boot_rom_mapped || (CGB_BOOT_ROM_HOLE.0..=CGB_BOOT_ROM_HOLE.1).contains(&address) {
+            return None;
+        }
+        self.boot_rom.get(address - BOOT_LOCATION).copied()
+    }
+
+    //Echo RAM is the work RAM seen again through incomplete address decoding
+    fn echo_ram_mirror(address: u16) -> usize {
+        address as usize - (ECHO_RAM_LOCATION.0 - WRAM1_LOCATION.0)
+    }
+
+    //Writes to 0xFEA0-0xFEFF are ignored and what reads return depends on the model. Monochrome
+    //models return 0, the CGB repeats the upper nibble of the lower address byte
+    fn read_not_usable(&self, address: u16) -> u8 {
+        if self.model.is_cgb() {
+            let nibble = (address as u8) & 0xF0;
+            nibble | (nibble >> 4)
+        } else {
+            0x00
+        }
+    }
+
+    //CPU read. While OAM DMA runs only the IO registers and HRAM are reliably accessible
+    pub fn read_8(&self, address: u16) -> u8 {
+        match self.dma.conflict(address) {
+            Some(value) => value,
+            None if self.ppu_conflict(address) => 0xFF,
+            None => self.read_bus(address)
+        }
+    }
+
+    //The PPU holds VRAM while drawing and OAM from the start of the OAM scan, locking out the CPU
+    fn ppu_conflict(&self, address: u16) -> bool {
+        match MemoryLocation::from_address(address) {
+            MemoryLocation::VideoRAM => !self.screen.vram_accessible(),
+            MemoryLocation::SpriteTable => !self.screen.oam_accessible(),
+            _ => false
+        }
+    }
+
+    fn read_bus(&self, address: u16) -> u8 {
+        match MemoryLocation::from_address(address) {
+            MemoryLocation::RomBank0 | MemoryLocation::RomBank1 => {
+                if let Some(value) = self.read_boot_rom(address) {
+                    return value;
+                }
+                match self.cartridge {
+                    Some(ref cartridge) => cartridge.read_rom(address),
+                    None => 0xFF
+                }
+            },
+            MemoryLocation::ExternalRAM => {
+                match self.cartridge {
+                    Some(ref cartridge) => cartridge.read_ram(address),
+                    None => 0xFF
+                }
+            },
+            MemoryLocation::VideoRAM => self.screen.read_vram(address as usize - VRAM_LOCATION.0),
+            MemoryLocation::SpriteTable => self.screen.read_oam(address as usize - SPRITE_TABLE_LOCATION.0),
+            MemoryLocation::EchoRam => self.bytes[Memory::echo_ram_mirror(address)],
+            MemoryLocation::NotUsable => self.read_not_usable(address),
+            MemoryLocation::IORegisters => self.read_io(address),
+            MemoryLocation::InterruptEnableRegister => self.interrupts.read_ie(),
+            _ => self.bytes[address as usize]
+        }
     }
 
     pub fn read_16(&self, address:u16) -> u16 {
-        let lower = self.bytes[address as usize];
-        let upper = self.bytes[(address+1) as usize];
+        let lower = self.read_8(address);
+        let upper = self.read_8(address.wrapping_add(1));
         ((upper as u16) << 8) | lower as u16
     }
 
-    pub fn write_8(&mut self, address:u16,value:u8) {
-        self.bytes[address as usize] = value;
+    //CPU write, dropped when it collides with a running OAM DMA transfer or the PPU
+    pub fn write_8(&mut self, address: u16, value: u8) {
+        if self.dma.conflict(address).is_none() && !self.ppu_conflict(address) {
+            self.write_bus(address, value);
+        }
+    }
+
+    fn write_bus(&mut self, address:u16,value:u8) {
+        match MemoryLocation::from_address(address) {
+            //Cartridge ROM is read only, writes go to the memory bank controller's registers
+            MemoryLocation::RomBank0 | MemoryLocation::RomBank1 => {
+                if let Some(ref mut cartridge) = self.cartridge {
+                    cartridge.write_rom(address, value);
+                }
+            },
+            MemoryLocation::ExternalRAM => {
+                if let Some(ref mut cartridge) = self.cartridge {
+                    cartridge.write_ram(address, value);
+                }
+            },
+            MemoryLocation::VideoRAM => self.screen.write_vram(address as usize - VRAM_LOCATION.0, value),
+            MemoryLocation::SpriteTable => self.screen.write_oam(address as usize - SPRITE_TABLE_LOCATION.0, value),
+            MemoryLocation::EchoRam => self.bytes[Memory::echo_ram_mirror(address)] = value,
+            MemoryLocation::NotUsable => {},
+            MemoryLocation::IORegisters => self.write_io(address, value),
+            MemoryLocation::InterruptEnableRegister => self.interrupts.write_ie(value),
+            _ => self.bytes[address as usize] = value
+        }
+    }
+
+    pub fn write_16(&mut self, address:u16,value:u16) {
+        self.write_8(address,(value & 0x00FF) as u8);
+        self.write_8(address.wrapping_add(1),(value >> 8) as u8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::interrupts::Interrupt;
+    use super::super::cartridge::ROM_BANK_SIZE;
+
+    fn test_cartridge() -> Cartridge {
+        let mut rom: Vec<u8> = (0..2 * ROM_BANK_SIZE).map(|i| (i & 0xFF) as u8 ^ 0xAA).collect();
+        rom[0x0134..=0x014C].fill(0);
+        rom[0x0147] = 0x00;
+        rom[0x0148] = 0x00;
+        rom[0x0149] = 0x00;
+        rom[0x014D] = super::super::cartridge::header_checksum(&rom);
+        Cartridge::from_bytes(rom).unwrap()
+    }
+
+    #[test]
+    fn boot_rom_overlay() {
+        let mut memory = Memory::new();
+        memory.load_cartridge(test_cartridge());
+        memory.load_boot_rom(&[0x31; 0x100]);
+        assert_eq!(memory.read_8(0x0000),0x31);
+        assert_eq!(memory.read_8(0x00FF),0x31);
+        assert_eq!(memory.read_8(0x0100),0xAA);
+        memory.write_8(0xFF50,0x00);
+        assert_eq!(memory.read_8(0x0000),0x31);
+        memory.write_8(0xFF50,0x01);
+        assert_eq!(memory.read_8(0x0000),0xAA);
+        memory.write_8(0xFF50,0x00);
+        assert_eq!(memory.read_8(0x0000),0xAA);
+        assert_eq!(memory.read_8(0x00FF),0xFF ^ 0xAA);
+    }
+
+    #[test]
+    fn echo_ram_mirrors_work_ram() {
+        let mut memory = Memory::new();
+        memory.write_8(0xC123,0x45);
+        assert_eq!(memory.read_8(0xE123),0x45);
+        memory.write_8(0xFDFF,0x67);
+        assert_eq!(memory.read_8(0xDDFF),0x67);
+        //0xDE00-0xDFFF has no mirror
+        memory.write_8(0xDE00,0x89);
+        assert_eq!(memory.read_8(0xFE00),0x00);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn not_usable_region() {
+        let mut memory = Memory::new();
+        memory.write_8(0xFEA0,0x12);
+        assert_eq!(memory.read_8(0xFEA0),0x00);
+        assert_eq!(memory.read_8(0xFEFF),0x00);
+        memory.set_model(Model::Cgb);
+        assert_eq!(memory.read_8(0xFEA5),0xAA);
+        assert_eq!(memory.read_8(0xFEF0),0xFF);
+    }
+
+    #[test]
+    fn rom_is_read_only() {
+        let mut memory = Memory::new();
+        memory.load_cartridge(test_cartridge());
+        memory.write_8(0x0150,0x00);
+        memory.write_8(0x4000,0x00);
+        assert_eq!(memory.read_8(0x0150),0x50 ^ 0xAA);
+        assert_eq!(memory.read_8(0x4000),0xAA);
+    }
+
+    #[test]
+    fn oam_dma_transfer() {
+        let mut memory = Memory::new();
+        for i in 0..0xA0 {
+            memory.write_8(0xC100 + i,i as u8);
+        }
+        memory.write_8(0x8000,0x99);
+        memory.write_8(0xFF80,0x77);
+        memory.write_8(0xFF46,0xC1);
+        assert_eq!(memory.read_8(0xFF46),0xC1);
+        memory.tick(1, 0);
+        memory.tick(1, 0);
+        //OAM is blocked and work RAM shares the external bus with the transfer
+        assert_eq!(memory.read_8(0xFE00),0xFF);
+        assert_eq!(memory.read_8(0xC000),0x00);
+        memory.tick(1, 0);
+        assert_eq!(memory.read_8(0xD000),0x01);
+        memory.write_8(0xC000,0x55);
+        //HRAM and the video bus are unaffected
+        assert_eq!(memory.read_8(0xFF80),0x77);
+        assert_eq!(memory.read_8(0x8000),0x99);
+        memory.tick(157, 0);
+        assert_eq!(memory.read_8(0xFE00),0xFF);
+        memory.tick(1, 0);
+        assert_eq!(memory.read_8(0xFE00),0x00);
+        assert_eq!(memory.read_8(0xFE9F),0x9F);
+        assert_eq!(memory.read_8(0xC000),0x00);
+    }
+
+    #[test]
+    fn oam_dma_source_above_work_ram_reads_echo() {
+        let mut memory = Memory::new();
+        memory.write_8(0xDE00,0x42);
+        memory.write_8(0xFF46,0xFE);
+        memory.tick(161, 0);
+        assert_eq!(memory.read_8(0xFE00),0x42);
+    }
+
+    #[test]
+    fn set_io_register_skips_write_side_effects() {
+        let mut memory = Memory::new();
+        //The APU is still off, which would drop a normal write
+        memory.set_io_register(0xFF12,0xF3);
+        memory.set_io_register(0xFF26,0xF1);
+        assert_eq!(memory.read_8(0xFF12),0xF3);
+        //Setting SC does not start a transfer
+        memory.set_io_register(0xFF02,0x81);
+        memory.tick(8 * 128, 0);
+        assert!(!memory.interrupts.is_requested(Interrupt::Serial));
+        //Setting LCDC does not run the LCD enable logic
+        memory.set_io_register(0xFF40,0x91);
+        assert_eq!(memory.read_8(0xFF41) & 0x03,0x00);
+    }
+
+    #[test]
+    fn io_registers_are_routed_to_components() {
+        let mut memory = Memory::new();
+        memory.set_io_register(0xFF04,0xAB);
+        assert_eq!(memory.read_8(0xFF04),0xAB);
+        memory.tick(64, 0);
+        assert_eq!(memory.read_8(0xFF04),0xAC);
+        memory.write_8(0xFF04,0x55);
+        assert_eq!(memory.read_8(0xFF04),0x00);
+        memory.write_8(0xFF07,0x00);
+        assert_eq!(memory.read_8(0xFF07),0xF8);
+        memory.write_8(0xFF00,0x00);
+        assert_eq!(memory.read_8(0xFF00),0xCF);
+        memory.write_8(0xFF41,0xFF);
+        assert_eq!(memory.read_8(0xFF41) & 0xF8,0xF8);
+        memory.write_8(0xFF44,0x12);
+        assert_eq!(memory.read_8(0xFF44),0x00);
+        //Unmapped registers and CGB registers on a DMG
+        memory.write_8(0xFF03,0x00);
+        assert_eq!(memory.read_8(0xFF03),0xFF);
+        memory.write_8(0xFF4F,0x00);
+        assert_eq!(memory.read_8(0xFF4F),0xFF);
+    }
+
+    #[test]
+    fn ppu_locks_video_memory_by_mode() {
+        let mut memory = Memory::new();
+        memory.write_8(0x8000,0x12);
+        memory.write_8(0xFE00,0x34);
+        memory.write_8(0xFF40,0x80);
+        //The first line after enabling has no OAM scan, so OAM is still open
+        assert_eq!(memory.read_8(0x8000),0x12);
+        assert_eq!(memory.read_8(0xFE00),0x34);
+        memory.write_8(0xFE00,0x56);
+        memory.tick(0, 80);
+        //Drawing
+        assert_eq!(memory.read_8(0x8000),0xFF);
+        assert_eq!(memory.read_8(0xFE00),0xFF);
+        memory.write_8(0x8000,0x78);
+        memory.write_8(0xFE00,0x78);
+        memory.tick(0, 172);
+        //HBlank
+        assert_eq!(memory.read_8(0x8000),0x12);
+        assert_eq!(memory.read_8(0xFE00),0x56);
+        memory.tick(0, 204);
+        //OAM scan of the next line
+        assert_eq!(memory.read_8(0x8000),0x12);
+        assert_eq!(memory.read_8(0xFE00),0xFF);
+        memory.write_8(0xFE00,0x78);
+        memory.write_8(0xFF40,0x00);
+        assert_eq!(memory.read_8(0xFE00),0x56);
+        memory.write_8(0xFE00,0x9A);
+        assert_eq!(memory.read_8(0xFE00),0x9A);
+    }
+
+    #[test]
+    fn cgb_io_registers() {
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        memory.write_8(0xFF4F,0x00);
+        assert_eq!(memory.read_8(0xFF4F),0xFE);
+        memory.write_8(0xFF70,0x03);
+        assert_eq!(memory.read_8(0xFF70),0xFB);
+        memory.write_8(0xFF02,0x00);
+        assert_eq!(memory.read_8(0xFF02),0x7C);
+    }
+
+    #[test]
+    fn key1_speed_bit_is_read_only() {
+        let mut memory = Memory::new();
+        memory.set_model(Model::Cgb);
+        memory.write_8(0xFF4D,0x80);
+        assert!(!memory.double_speed());
+        assert!(!memory.speed_switch_armed());
+        assert_eq!(memory.read_8(0xFF4D),0x7E);
+        memory.write_8(0xFF4D,0x01);
+        assert!(memory.speed_switch_armed());
+        memory.switch_speed();
+        assert!(memory.double_speed());
+        //Writing 0 clears the request but keeps the speed
+        memory.write_8(0xFF4D,0x00);
+        assert!(memory.double_speed());
+        assert_eq!(memory.read_8(0xFF4D),0xFE);
+    }
+
+    #[test]
+    fn cgb_boot_rom_leaves_header_visible() {
+        let mut memory = Memory::new();
+        memory.load_cartridge(test_cartridge());
+        memory.load_boot_rom(&[0x31; 0x900]);
+        assert_eq!(memory.read_8(0x0105),0x05 ^ 0xAA);
+        assert_eq!(memory.read_8(0x0200),0x31);
+        assert_eq!(memory.read_8(0x08FF),0x31);
+        assert_eq!(memory.read_8(0x0900),0xAA);
+    }
+}
@@ -0,0 +1,153 @@
+use super::registers::Registers;
+
+//Hardware revision being emulated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Model {
+    //Original DMG with the early boot ROM
+    Dmg0,
+    #[default]
+    Dmg,
+    //Game Boy Pocket
+    Mgb,
+    //Super Game Boy
+    Sgb,
+    //Game Boy Color
+    Cgb
+}
+
+//Address the boot ROM hands over to the cartridge at
+pub const ENTRY_POINT: u16 = 0x0100;
+const POST_BOOT_SP: u16 = 0xFFFE;
+
+impl Model {
+    //Parse the lowercase model name used on the command line
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "dmg0" => Some(Model::Dmg0),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "cgb" => Some(Model::Cgb),
+            _ => None
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        *self == Model::Cgb
+    }
+
+    //CPU registers as left by the boot ROM. On DMG and MGB the half carry and carry flags
+    //are only set when the cartridge header checksum is not zero
+    pub fn post_boot_registers(&self, header_checksum: u8) -> Registers {
+        let mut registers = Registers::new();
+        let (a, f, b, c, d, e, h, l) = match self {
+            Model::Dmg0 => (0x01, 0x00, 0xFF, 0x13, 0x00, 0xC1, 0x84, 0x03),
+            Model::Dmg => (0x01, 0x80, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Mgb => (0xFF, 0x80, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Sgb => (0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D)
+        };
+        let f = match self {
+            Model::Dmg | Model::Mgb if header_checksum != 0 => f | 0x30,
+            _ => f
+        };
+        registers.a = a;
+        registers.f = f.into();
+        registers.b = b;
+        registers.c = c;
+        registers.d = d;
+        registers.e = e;
+        registers.h = h;
+        registers.l = l;
+        registers.sp = POST_BOOT_SP;
+        registers.pc = ENTRY_POINT;
+        registers
+    }
+
+    //Hardware registers as left by the boot ROM, as (address, value) pairs
+    pub fn post_boot_io_registers(&self) -> Vec<(u16, u8)> {
+        //DIV depends on how long the boot ROM ran for
+        let div = match self {
+            Model::Dmg0 => 0x18,
+            Model::Dmg | Model::Mgb => 0xAB,
+            Model::Sgb => 0xD8,
+            Model::Cgb => 0x1E
+        };
+        let nr52 = if *self == Model::Sgb { 0xF0 } else { 0xF1 };
+        let mut io_registers = vec![
+            (0xFF00, 0xCF), // P1
+            (0xFF01, 0x00), // SB
+            (0xFF02, if self.is_cgb() { 0x7F } else { 0x7E }), // SC
+            (0xFF04, div), // DIV
+            (0xFF05, 0x00), // TIMA
+            (0xFF06, 0x00), // TMA
+            (0xFF07, 0xF8), // TAC
+            (0xFF0F, 0xE1), // IF
+            (0xFF10, 0x80), // NR10
+            (0xFF11, 0xBF), // NR11
+            (0xFF12, 0xF3), // NR12
+            (0xFF13, 0xFF), // NR13
+            (0xFF14, 0xBF), // NR14
+            (0xFF16, 0x3F), // NR21
+            (0xFF17, 0x00), // NR22
+            (0xFF18, 0xFF), // NR23
+            (0xFF19, 0xBF), // NR24
+            (0xFF1A, 0x7F), // NR30
+            (0xFF1B, 0xFF), // NR31
+            (0xFF1C, 0x9F), // NR32
+            (0xFF1D, 0xFF), // NR33
+            (0xFF1E, 0xBF), // NR34
+            (0xFF20, 0xFF), // NR41
+            (0xFF21, 0x00), // NR42
+            (0xFF22, 0x00), // NR43
+            (0xFF23, 0xBF), // NR44
+            (0xFF24, 0x77), // NR50
+            (0xFF25, 0xF3), // NR51
+            (0xFF26, nr52), // NR52
+            (0xFF40, 0x91), // LCDC
+            (0xFF41, if *self == Model::Dmg0 { 0x81 } else { 0x85 }), // STAT
+            (0xFF42, 0x00), // SCY
+            (0xFF43, 0x00), // SCX
+            (0xFF44, 0x00), // LY
+            (0xFF45, 0x00), // LYC
+            (0xFF46, if self.is_cgb() { 0x00 } else { 0xFF }), // DMA
+            (0xFF47, 0xFC), // BGP
+            (0xFF48, 0xFF), // OBP0
+            (0xFF49, 0xFF), // OBP1
+            (0xFF4A, 0x00), // WY
+            (0xFF4B, 0x00), // WX
+            (0xFFFF, 0x00) // IE
+        ];
+        if self.is_cgb() {
+            io_registers.extend_from_slice(&[
+                (0xFF4D, 0x7E), // KEY1
+                (0xFF4F, 0xFE), // VBK
+                (0xFF70, 0xF8) // SVBK
+            ]);
+        }
+        io_registers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::instruction::Register16;
+    #[test]
+    fn dmg_post_boot_registers() {
+        let registers = Model::Dmg.post_boot_registers(0x3A);
+        assert_eq!(registers.get_16(&Register16::AF),0x01B0);
+        assert_eq!(registers.get_16(&Register16::BC),0x0013);
+        assert_eq!(registers.get_16(&Register16::DE),0x00D8);
+        assert_eq!(registers.get_16(&Register16::HL),0x014D);
+        assert_eq!(registers.sp,0xFFFE);
+        assert_eq!(registers.pc,0x0100);
+    }
+    #[test]
+    fn zero_header_checksum_clears_carry_flags() {
+        let registers = Model::Dmg.post_boot_registers(0x00);
+        assert_eq!(registers.get_16(&Register16::AF),0x0180);
+        let registers = Model::Cgb.post_boot_registers(0x3A);
+        assert_eq!(registers.get_16(&Register16::AF),0x1180);
+    }
+}
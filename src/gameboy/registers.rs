@@ -6,6 +6,7 @@ const CARRY_FLAG_BYTE_POSITION: u8 = 4;
 use super::instruction::Register8;
 use super::instruction::Register16;
 
+#[derive(Clone, Copy)]
 pub struct FlagsRegister {
     pub zero: bool,
     pub subtract: bool,
@@ -38,6 +39,7 @@ impl std::convert::From<u8> for FlagsRegister {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Registers {
     pub a:u8,
     pub b:u8,
@@ -1,6 +1,855 @@
-const width: u8 = 160;
-const height: u8 = 144;
+use super::interrupts::{Interrupt, InterruptController};
+use fifo::PixelFifo;
 
+mod fifo;
+
+pub const WIDTH: usize = 160;
+pub const HEIGHT: usize = 144;
+
+const LCDC_ADDRESS: u16 = 0xFF40;
+const STAT_ADDRESS: u16 = 0xFF41;
+const SCY_ADDRESS: u16 = 0xFF42;
+const SCX_ADDRESS: u16 = 0xFF43;
+const LY_ADDRESS: u16 = 0xFF44;
+const LYC_ADDRESS: u16 = 0xFF45;
+const BGP_ADDRESS: u16 = 0xFF47;
+const OBP0_ADDRESS: u16 = 0xFF48;
+const OBP1_ADDRESS: u16 = 0xFF49;
+const WY_ADDRESS: u16 = 0xFF4A;
+const WX_ADDRESS: u16 = 0xFF4B;
+
+const VRAM_SIZE: usize = 0x2000;
+const OAM_SIZE: usize = 0xA0;
+
+const LCD_ENABLE_BIT: u8 = 0x80;
+const WINDOW_TILE_MAP_BIT: u8 = 0x40;
+const WINDOW_ENABLE_BIT: u8 = 0x20;
+//Set for unsigned tile numbers from 0x8000, clear for signed ones around 0x9000
+const TILE_DATA_BIT: u8 = 0x10;
+const BG_TILE_MAP_BIT: u8 = 0x08;
+const OBJ_SIZE_BIT: u8 = 0x04;
+const OBJ_ENABLE_BIT: u8 = 0x02;
+//On monochrome models this blanks both the background and the window
+const BG_ENABLE_BIT: u8 = 0x01;
+
+//VRAM offsets of the two 32x32 tile maps and of the two tile data areas
+const TILE_MAP_0: usize = 0x1800;
+const TILE_MAP_1: usize = 0x1C00;
+const TILE_DATA_UNSIGNED: usize = 0x0000;
+const TILE_DATA_SIGNED: usize = 0x1000;
+const TILE_SIZE: usize = 16;
+const TILE_MAP_WIDTH: usize = 32;
+
+//The window's left edge is at WX - 7
+const WINDOW_X_OFFSET: u8 = 7;
+
+//OAM holds 40 sprites of 4 bytes: Y + 16, X + 8, tile number and attributes
+const OAM_ENTRY_SIZE: usize = 4;
+const SPRITE_Y_OFFSET: i16 = 16;
+const SPRITE_X_OFFSET: i16 = 8;
+const SPRITES_PER_LINE: usize = 10;
+const BG_OVER_OBJ_BIT: u8 = 0x80;
+const Y_FLIP_BIT: u8 = 0x40;
+const X_FLIP_BIT: u8 = 0x20;
+const OBP1_BIT: u8 = 0x10;
+
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    //Screen position of the top left corner
+    x: i16,
+    y: i16,
+    tile: u8,
+    attributes: u8
+}
+
+//STAT bit 7 is unused, bits 3-6 select the interrupt sources and the rest is read only
+const STAT_UNUSED_BIT: u8 = 0x80;
+const STAT_WRITABLE_BITS: u8 = 0x78;
+const STAT_COINCIDENCE_BIT: u8 = 0x04;
+const STAT_HBLANK_SOURCE: u8 = 0x08;
+const STAT_VBLANK_SOURCE: u8 = 0x10;
+const STAT_OAM_SOURCE: u8 = 0x20;
+const STAT_LYC_SOURCE: u8 = 0x40;
+
+//Every line takes 456 dots: 80 of OAM scan, 172 of drawing and the rest in HBlank
+pub const DOTS_PER_LINE: u16 = 456;
+const OAM_SCAN_DOTS: u16 = 80;
+const DRAWING_DOTS: u16 = 172;
+//144 visible lines followed by 10 lines of VBlank
+pub const LINES_PER_FRAME: u8 = 154;
+
+//PPU mode reported in the lower 2 bits of STAT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing
+}
+
+impl Mode {
+    fn bits(&self) -> u8 {
+        match self {
+            Mode::HBlank => 0,
+            Mode::VBlank => 1,
+            Mode::OamScan => 2,
+            Mode::Drawing => 3
+        }
+    }
+}
+
+//How pixels are produced. The scanline renderer draws a whole line at the start of HBlank, the pixel
+//FIFO renderer draws a pixel per dot like the hardware, so mid-line register writes take effect and
+//the length of mode 3 varies with scrolling, the window and sprites
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Renderer {
+    #[default]
+    Scanline,
+    PixelFifo
+}
+
+//The PPU, with its registers at 0xFF40-0xFF4B except for the OAM DMA register at 0xFF46, VRAM and OAM
 pub struct Screen {
+    lcdc: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    renderer: Renderer,
+    fifo: PixelFifo,
+    //Position within the current line
+    dot: u16,
+    //The window starts showing once LY has matched WY during the frame, and then draws its own
+    //lines in order, skipping none even when it is hidden for a few lines
+    window_triggered: bool,
+    window_line: u8,
+    //STAT interrupt line, the OR of all enabled sources. The interrupt fires when it goes high
+    stat_line: bool,
+    vram: Vec<u8>,
+    oam: Vec<u8>,
+    //Shade indices 0-3 of the last frame, row by row
+    framebuffer: Vec<u8>
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Self {
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            renderer: Renderer::default(),
+            fifo: PixelFifo::new(),
+            dot: 0,
+            window_triggered: false,
+            window_line: 0,
+            stat_line: false,
+            vram: vec![0; VRAM_SIZE],
+            oam: vec![0; OAM_SIZE],
+            framebuffer: vec![0; WIDTH * HEIGHT]
+        }
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & LCD_ENABLE_BIT != 0
+    }
+
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.framebuffer
+    }
+
+    //With the LCD off the CPU has VRAM and OAM to itself
+    pub fn vram_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode != Mode::Drawing
+    }
+
+    pub fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled() || matches!(self.mode, Mode::HBlank | Mode::VBlank)
+    }
+
+    //Offsets relative to 0x8000 and 0xFE00
+    pub fn read_vram(&self, offset: usize) -> u8 {
+        self.vram[offset]
+    }
+
+    pub fn write_vram(&mut self, offset: usize, value: u8) {
+        self.vram[offset] = value;
+    }
+
+    pub fn read_oam(&self, offset: usize) -> u8 {
+        self.oam[offset]
+    }
+
+    pub fn write_oam(&mut self, offset: usize, value: u8) {
+        self.oam[offset] = value;
+    }
+
+    fn update_stat_line(&mut self, interrupts: &mut InterruptController) {
+        let line = self.lcd_enabled() && (match self.mode {
+            Mode::HBlank => self.stat & STAT_HBLANK_SOURCE != 0,
+            Mode::VBlank => self.stat & STAT_VBLANK_SOURCE != 0,
+            Mode::OamScan => self.stat & STAT_OAM_SOURCE != 0,
+            Mode::Drawing => false
+        } || self.stat & STAT_LYC_SOURCE != 0 && self.ly == self.lyc);
+        if line && !self.stat_line {
+            interrupts.request(Interrupt::LcdStat);
+        }
+        self.stat_line = line;
+    }
+
+    fn enter_mode(&mut self, mode: Mode, interrupts: &mut InterruptController) {
+        self.mode = mode;
+        match mode {
+            Mode::HBlank => match self.renderer {
+                Renderer::Scanline => self.render_line(),
+                Renderer::PixelFifo => self.finish_fifo_line()
+            },
+            Mode::Drawing if self.renderer == Renderer::PixelFifo => self.start_fifo_line(),
+            Mode::VBlank => {
+                self.window_triggered = false;
+                self.window_line = 0;
+                interrupts.request(Interrupt::VBlank);
+            },
+            Mode::OamScan if self.ly == self.wy => self.window_triggered = true,
+            _ => {}
+        }
+    }
+
+    //Color index 0-3 of a pixel in the 256x256 layer described by a tile map
+    fn tile_map_pixel(&self, tile_map: usize, x: u8, y: u8) -> u8 {
+        let tile_number = self.vram[tile_map + (y as usize / 8) * TILE_MAP_WIDTH + x as usize / 8];
+        self.tile_pixel(self.tile_address(tile_number), x % 8, y % 8)
+    }
+
+    //VRAM offset of a background or window tile
+    fn tile_address(&self, tile_number: u8) -> usize {
+        if self.lcdc & TILE_DATA_BIT != 0 {
+            TILE_DATA_UNSIGNED + tile_number as usize * TILE_SIZE
+        } else {
+            (TILE_DATA_SIGNED as isize + tile_number as i8 as isize * TILE_SIZE as isize) as usize
+        }
+    }
+
+    fn window_visible(&self) -> bool {
+        self.lcdc & WINDOW_ENABLE_BIT != 0 && self.window_triggered && self.wx as usize <= WIDTH + 6
+    }
+
+    //Each tile row is two bytes, holding the low and high bits of 8 pixels with the leftmost in bit 7
+    fn tile_pixel(&self, tile: usize, x: u8, y: u8) -> u8 {
+        let low = self.vram[tile + y as usize * 2];
+        let high = self.vram[tile + y as usize * 2 + 1];
+        let bit = 7 - x;
+        ((high >> bit) & 0x01) << 1 | (low >> bit) & 0x01
+    }
+
+    //Palettes hold a 2 bit shade for each of the 4 color indices
+    fn shade(palette: u8, color: u8) -> u8 {
+        (palette >> (color * 2)) & 0x03
+    }
+
+    fn tile_map(&self, bit: u8) -> usize {
+        if self.lcdc & bit != 0 { TILE_MAP_1 } else { TILE_MAP_0 }
+    }
+
+    //Fill the framebuffer row for the current line
+    fn render_line(&mut self) {
+        let start = self.ly as usize * WIDTH;
+        let mut line = [0; WIDTH];
+        //With the background off the line is blank white whatever BGP says, and sprites see color 0
+        let background_enabled = self.lcdc & BG_ENABLE_BIT != 0;
+        if background_enabled {
+            self.render_background(&mut line);
+        }
+        for (pixel, color) in self.framebuffer[start..start + WIDTH].iter_mut().zip(line.iter()) {
+            *pixel = if background_enabled { Screen::shade(self.bgp, *color) } else { 0 };
+        }
+        if self.lcdc & OBJ_ENABLE_BIT != 0 {
+            self.render_sprites(&line);
+        }
+    }
+
+    fn sprite_height(&self) -> i16 {
+        if self.lcdc & OBJ_SIZE_BIT != 0 { 16 } else { 8 }
+    }
+
+    //The first 10 sprites in OAM order overlapping the current line, whatever their X position
+    fn scan_oam(&self) -> Vec<Sprite> {
+        let height = self.sprite_height();
+        let ly = self.ly as i16;
+        self.oam.chunks(OAM_ENTRY_SIZE)
+            .map(|entry| Sprite {
+                y: entry[0] as i16 - SPRITE_Y_OFFSET,
+                x: entry[1] as i16 - SPRITE_X_OFFSET,
+                tile: entry[2],
+                attributes: entry[3]
+            })
+            .filter(|sprite| ly >= sprite.y && ly < sprite.y + height)
+            .take(SPRITES_PER_LINE)
+            .collect()
+    }
+
+    //Color index of a sprite pixel, given its position within the sprite
+    fn sprite_pixel(&self, sprite: &Sprite, x: u8, y: u8) -> u8 {
+        let height = self.sprite_height() as u8;
+        let x = if sprite.attributes & X_FLIP_BIT != 0 { 7 - x } else { x };
+        let y = if sprite.attributes & Y_FLIP_BIT != 0 { height - 1 - y } else { y };
+        //Tall sprites ignore the lowest bit of the tile number and continue into the next tile
+        let tile = if height == 16 { sprite.tile & 0xFE } else { sprite.tile };
+        self.tile_pixel(TILE_DATA_UNSIGNED + tile as usize * TILE_SIZE + (y as usize / 8) * TILE_SIZE, x, y % 8)
+    }
+
+    //Draw sprites over the line, given the background color indices they may hide behind
+    fn render_sprites(&mut self, background: &[u8; WIDTH]) {
+        let mut sprites = self.scan_oam();
+        //On monochrome models the sprite with the lower X wins, then the one earlier in OAM. The sort is
+        //stable, so OAM order is kept among equal X
+        sprites.sort_by_key(|sprite| sprite.x);
+        let start = self.ly as usize * WIDTH;
+        for (x, background_color) in background.iter().enumerate() {
+            let screen_x = x as i16;
+            let pixel = sprites.iter()
+                .filter(|sprite| screen_x >= sprite.x && screen_x < sprite.x + 8)
+                .map(|sprite| (sprite, self.sprite_pixel(sprite, (screen_x - sprite.x) as u8, (self.ly as i16 - sprite.y) as u8)))
+                .find(|(_, color)| *color != 0);
+            if let Some((sprite, color)) = pixel {
+                if sprite.attributes & BG_OVER_OBJ_BIT != 0 && *background_color != 0 {
+                    continue;
+                }
+                let palette = if sprite.attributes & OBP1_BIT != 0 { self.obp1 } else { self.obp0 };
+                self.framebuffer[start + x] = Screen::shade(palette, color);
+            }
+        }
+    }
+
+    //Background color indices for the current line, with the window drawn over them
+    fn render_background(&mut self, line: &mut [u8; WIDTH]) {
+        let background = self.tile_map(BG_TILE_MAP_BIT);
+        let y = self.ly.wrapping_add(self.scy);
+        for (x, color) in line.iter_mut().enumerate() {
+            *color = self.tile_map_pixel(background, (x as u8).wrapping_add(self.scx), y);
+        }
+
+        if !self.window_visible() {
+            return;
+        }
+        let window = self.tile_map(WINDOW_TILE_MAP_BIT);
+        let left = self.wx.saturating_sub(WINDOW_X_OFFSET) as usize;
+        //With WX below 7 the window is cut off on the left
+        let skipped = WINDOW_X_OFFSET.saturating_sub(self.wx);
+        for (x, color) in line.iter_mut().enumerate().skip(left) {
+            let window_x = (x - left) as u8 + skipped;
+            *color = self.tile_map_pixel(window, window_x, self.window_line);
+        }
+        self.window_line += 1;
+    }
+
+    //Advance by one dot, a T-cycle at normal speed
+    fn step(&mut self, interrupts: &mut InterruptController) {
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            if self.ly == HEIGHT as u8 {
+                self.enter_mode(Mode::VBlank, interrupts);
+            } else if (self.ly as usize) < HEIGHT {
+                self.enter_mode(Mode::OamScan, interrupts);
+            }
+        //HBlank this early only happens on the first line after the LCD is switched on
+        } else if matches!(self.mode, Mode::OamScan | Mode::HBlank) && self.dot == OAM_SCAN_DOTS {
+            self.enter_mode(Mode::Drawing, interrupts);
+        } else if self.mode == Mode::Drawing {
+            let finished = match self.renderer {
+                Renderer::Scanline => self.dot == OAM_SCAN_DOTS + DRAWING_DOTS,
+                Renderer::PixelFifo => self.step_fifo()
+            };
+            if finished {
+                self.enter_mode(Mode::HBlank, interrupts);
+            }
+        }
+        self.update_stat_line(interrupts);
+    }
+
+    pub fn tick(&mut self, dots: u64, interrupts: &mut InterruptController) {
+        if !self.lcd_enabled() {
+            return;
+        }
+        for _ in 0..dots {
+            self.step(interrupts);
+        }
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = if self.ly == self.lyc { STAT_COINCIDENCE_BIT } else { 0 };
+        STAT_UNUSED_BIT | self.stat | coincidence | self.mode.bits()
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            LCDC_ADDRESS => self.lcdc,
+            STAT_ADDRESS => self.read_stat(),
+            SCY_ADDRESS => self.scy,
+            SCX_ADDRESS => self.scx,
+            LY_ADDRESS => self.ly,
+            LYC_ADDRESS => self.lyc,
+            BGP_ADDRESS => self.bgp,
+            OBP0_ADDRESS => self.obp0,
+            OBP1_ADDRESS => self.obp1,
+            WY_ADDRESS => self.wy,
+            WX_ADDRESS => self.wx,
+            _ => 0xFF
+        }
+    }
+
+    fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+        //Switching the LCD off resets it to the start of the frame, switching it on starts a new one
+        if was_enabled && !self.lcd_enabled() {
+            self.ly = 0;
+            self.dot = 0;
+            self.mode = Mode::HBlank;
+            self.window_triggered = false;
+            self.window_line = 0;
+        } else if !was_enabled && self.lcd_enabled() {
+            //The first line has no OAM scan, STAT reports mode 0 and OAM stays accessible until drawing starts
+            self.mode = Mode::HBlank;
+            self.window_triggered = self.ly == self.wy;
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8, interrupts: &mut InterruptController) {
+        match address {
+            LCDC_ADDRESS => self.write_lcdc(value),
+            //LY is read only
+            LY_ADDRESS => {},
+            _ => self.set_register(address, value)
+        }
+        //Enabling a source or changing LYC can raise the STAT line straight away
+        self.update_stat_line(interrupts);
+    }
+
+    pub fn set_register(&mut self, address: u16, value: u8) {
+        match address {
+            LCDC_ADDRESS => self.lcdc = value,
+            STAT_ADDRESS => self.stat = value & STAT_WRITABLE_BITS,
+            SCY_ADDRESS => self.scy = value,
+            SCX_ADDRESS => self.scx = value,
+            LY_ADDRESS => self.ly = value,
+            LYC_ADDRESS => self.lyc = value,
+            BGP_ADDRESS => self.bgp = value,
+            OBP0_ADDRESS => self.obp0 = value,
+            OBP1_ADDRESS => self.obp1 = value,
+            WY_ADDRESS => self.wy = value,
+            WX_ADDRESS => self.wx = value,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn enabled_screen() -> (Screen, InterruptController) {
+        let mut screen = Screen::new();
+        let mut interrupts = InterruptController::new();
+        screen.write(LCDC_ADDRESS, 0x91, &mut interrupts);
+        (screen, interrupts)
+    }
+
+    #[test]
+    fn mode_timing() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        //The first line after enabling the LCD has no OAM scan
+        assert_eq!(screen.mode,Mode::HBlank);
+        assert_eq!(screen.read(STAT_ADDRESS) & 0x03,0);
+        screen.tick(79, &mut interrupts);
+        assert_eq!(screen.mode,Mode::HBlank);
+        screen.tick(1, &mut interrupts);
+        assert_eq!(screen.mode,Mode::Drawing);
+        screen.tick(172, &mut interrupts);
+        assert_eq!(screen.mode,Mode::HBlank);
+        screen.tick(204, &mut interrupts);
+        assert_eq!(screen.mode,Mode::OamScan);
+        assert_eq!(screen.read(LY_ADDRESS),1);
+        assert_eq!(screen.read(STAT_ADDRESS) & 0x03,2);
+    }
+
+    #[test]
+    fn vblank_and_frame_length() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.tick(143 * DOTS_PER_LINE as u64 + DOTS_PER_LINE as u64 - 1, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::VBlank));
+        screen.tick(1, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),144);
+        assert_eq!(screen.mode,Mode::VBlank);
+        assert!(interrupts.is_requested(Interrupt::VBlank));
+        screen.tick(10 * DOTS_PER_LINE as u64 - 1, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),153);
+        screen.tick(1, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),0);
+        assert_eq!(screen.mode,Mode::OamScan);
+    }
+
+    #[test]
+    fn lyc_stat_interrupt() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.write(LYC_ADDRESS, 2, &mut interrupts);
+        screen.write(STAT_ADDRESS, STAT_LYC_SOURCE, &mut interrupts);
+        screen.tick(2 * DOTS_PER_LINE as u64 - 1, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::LcdStat));
+        assert_eq!(screen.read(STAT_ADDRESS) & STAT_COINCIDENCE_BIT,0);
+        screen.tick(1, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::LcdStat));
+        assert_eq!(screen.read(STAT_ADDRESS) & STAT_COINCIDENCE_BIT,STAT_COINCIDENCE_BIT);
+    }
+
+    #[test]
+    fn stat_sources_share_one_line() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.write(STAT_ADDRESS, STAT_HBLANK_SOURCE | STAT_OAM_SOURCE, &mut interrupts);
+        //Entering OAM scan straight from HBlank keeps the line high, so no second interrupt
+        screen.tick(OAM_SCAN_DOTS as u64 + DRAWING_DOTS as u64, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::LcdStat));
+        interrupts.acknowledge(Interrupt::LcdStat);
+        screen.tick(DOTS_PER_LINE as u64 - OAM_SCAN_DOTS as u64 - DRAWING_DOTS as u64, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::LcdStat));
+    }
+
+    //Tile with every pixel of the given color
+    fn solid_tile(screen: &mut Screen, tile: usize, color: u8) {
+        for row in 0..8 {
+            screen.vram[tile + row * 2] = if color & 0x01 != 0 { 0xFF } else { 0x00 };
+            screen.vram[tile + row * 2 + 1] = if color & 0x02 != 0 { 0xFF } else { 0x00 };
+        }
+    }
+
+    fn render(screen: &mut Screen, ly: u8) -> Vec<u8> {
+        screen.ly = ly;
+        screen.render_line();
+        screen.framebuffer[ly as usize * WIDTH..(ly as usize + 1) * WIDTH].to_vec()
+    }
+
+    #[test]
+    fn tile_pixel_bits() {
+        let mut screen = Screen::new();
+        //Leftmost pixel color 3, next color 2, next color 1
+        screen.vram[0] = 0b1010_0000;
+        screen.vram[1] = 0b1100_0000;
+        assert_eq!(screen.tile_pixel(0, 0, 0),3);
+        assert_eq!(screen.tile_pixel(0, 1, 0),2);
+        assert_eq!(screen.tile_pixel(0, 2, 0),1);
+        assert_eq!(screen.tile_pixel(0, 3, 0),0);
+    }
+
+    #[test]
+    fn background_scrolling_and_palette() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        //BGP maps color 1 to shade 3 and color 0 to shade 0
+        screen.write(BGP_ADDRESS, 0b0000_1100, &mut interrupts);
+        solid_tile(&mut screen, TILE_DATA_UNSIGNED + TILE_SIZE, 1);
+        //Tile 1 at map column 1, row 1
+        screen.vram[TILE_MAP_0 + TILE_MAP_WIDTH + 1] = 1;
+        let line = render(&mut screen, 8);
+        assert_eq!(&line[0..8],&[0; 8]);
+        assert_eq!(&line[8..16],&[3; 8]);
+        screen.write(SCX_ADDRESS, 4, &mut interrupts);
+        screen.write(SCY_ADDRESS, 250, &mut interrupts);
+        let line = render(&mut screen, 14);
+        assert_eq!(&line[3..5],&[0, 3]);
+        assert_eq!(line[12],0);
+        //Switching to the other tile map
+        screen.write(LCDC_ADDRESS, 0x91 | BG_TILE_MAP_BIT, &mut interrupts);
+        assert_eq!(render(&mut screen, 14)[4],0);
+        //Background off is white whatever BGP says
+        screen.write(LCDC_ADDRESS, 0x90, &mut interrupts);
+        screen.write(BGP_ADDRESS, 0b1111_1111, &mut interrupts);
+        assert_eq!(render(&mut screen, 14)[4],0);
+    }
+
+    #[test]
+    fn signed_tile_addressing() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.write(BGP_ADDRESS, 0b1110_0100, &mut interrupts);
+        screen.write(LCDC_ADDRESS, 0x81, &mut interrupts);
+        //Tile 0xFF is just below 0x9000, tile 0x00 at 0x9000
+        solid_tile(&mut screen, TILE_DATA_SIGNED - TILE_SIZE, 2);
+        solid_tile(&mut screen, TILE_DATA_SIGNED, 1);
+        screen.vram[TILE_MAP_0] = 0xFF;
+        let line = render(&mut screen, 0);
+        assert_eq!(line[0],2);
+        assert_eq!(line[8],1);
+    }
+
+    #[test]
+    fn window_position_and_line_counter() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.write(BGP_ADDRESS, 0b1110_0100, &mut interrupts);
+        screen.write(LCDC_ADDRESS, 0x91 | WINDOW_ENABLE_BIT | WINDOW_TILE_MAP_BIT, &mut interrupts);
+        screen.write(WY_ADDRESS, 10, &mut interrupts);
+        screen.write(WX_ADDRESS, 27, &mut interrupts);
+        //Enabling the LCD matched the initial WY of 0
+        screen.window_triggered = false;
+        solid_tile(&mut screen, TILE_DATA_UNSIGNED + TILE_SIZE, 2);
+        solid_tile(&mut screen, TILE_DATA_UNSIGNED + 2 * TILE_SIZE, 3);
+        screen.vram[TILE_MAP_1] = 1;
+        screen.vram[TILE_MAP_1 + TILE_MAP_WIDTH] = 2;
+        assert_eq!(render(&mut screen, 9)[20],0);
+        screen.window_triggered = true;
+        let line = render(&mut screen, 10);
+        assert_eq!(line[19],0);
+        assert_eq!(line[20],2);
+        //Hiding the window for some lines pauses its line counter
+        screen.write(LCDC_ADDRESS, 0x91, &mut interrupts);
+        for ly in 11..20 {
+            render(&mut screen, ly);
+        }
+        screen.write(LCDC_ADDRESS, 0x91 | WINDOW_ENABLE_BIT | WINDOW_TILE_MAP_BIT, &mut interrupts);
+        for ly in 20..27 {
+            assert_eq!(render(&mut screen, ly)[20],2);
+        }
+        assert_eq!(render(&mut screen, 27)[20],3);
+    }
+
+    fn set_sprite(screen: &mut Screen, index: usize, x: u8, y: u8, tile: u8, attributes: u8) {
+        screen.oam[index * OAM_ENTRY_SIZE..(index + 1) * OAM_ENTRY_SIZE].copy_from_slice(&[y + 16, x + 8, tile, attributes]);
+    }
+
+    fn sprite_screen() -> (Screen, InterruptController) {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.write(BGP_ADDRESS, 0b1110_0100, &mut interrupts);
+        screen.write(OBP0_ADDRESS, 0b1110_0100, &mut interrupts);
+        screen.write(OBP1_ADDRESS, 0b0001_1011, &mut interrupts);
+        screen.write(LCDC_ADDRESS, 0x91 | OBJ_ENABLE_BIT, &mut interrupts);
+        (screen, interrupts)
+    }
+
+    #[test]
+    fn sprite_palettes_and_transparency() {
+        let (mut screen, _) = sprite_screen();
+        solid_tile(&mut screen, TILE_SIZE, 1);
+        //Left half of tile 2 is transparent
+        for row in 0..8 {
+            screen.vram[2 * TILE_SIZE + row * 2] = 0x0F;
+            screen.vram[2 * TILE_SIZE + row * 2 + 1] = 0x0F;
+        }
+        set_sprite(&mut screen, 0, 10, 20, 1, 0);
+        set_sprite(&mut screen, 1, 30, 20, 1, OBP1_BIT);
+        set_sprite(&mut screen, 2, 50, 20, 2, 0);
+        let line = render(&mut screen, 20);
+        assert_eq!(line[9],0);
+        assert_eq!(&line[10..18],&[1; 8]);
+        assert_eq!(&line[30..38],&[2; 8]);
+        assert_eq!(&line[50..58],&[0, 0, 0, 0, 3, 3, 3, 3]);
+        assert_eq!(render(&mut screen, 28)[10],0);
+        //Sprites off
+        screen.lcdc &= !OBJ_ENABLE_BIT;
+        assert_eq!(render(&mut screen, 20)[10],0);
+    }
+
+    #[test]
+    fn sprite_flipping_and_tall_sprites() {
+        let (mut screen, _) = sprite_screen();
+        //Tile 4 has a single pixel of color 3 at its top left, tile 5 one of color 2 at its bottom right
+        screen.vram[4 * TILE_SIZE] = 0x80;
+        screen.vram[4 * TILE_SIZE + 1] = 0x80;
+        screen.vram[5 * TILE_SIZE + 14 + 1] = 0x01;
+        set_sprite(&mut screen, 0, 0, 0, 4, X_FLIP_BIT);
+        assert_eq!(render(&mut screen, 0)[7],3);
+        set_sprite(&mut screen, 0, 0, 0, 4, Y_FLIP_BIT);
+        assert_eq!(render(&mut screen, 7)[0],3);
+        screen.lcdc |= OBJ_SIZE_BIT;
+        //Tile 5 is used as the lower half even when the sprite names it
+        set_sprite(&mut screen, 0, 0, 0, 5, 0);
+        assert_eq!(render(&mut screen, 0)[0],3);
+        assert_eq!(render(&mut screen, 15)[7],2);
+        set_sprite(&mut screen, 0, 0, 0, 5, Y_FLIP_BIT | X_FLIP_BIT);
+        assert_eq!(render(&mut screen, 0)[0],2);
+        assert_eq!(render(&mut screen, 15)[7],3);
+    }
+
+    #[test]
+    fn sprite_priority() {
+        let (mut screen, mut interrupts) = sprite_screen();
+        solid_tile(&mut screen, TILE_SIZE, 1);
+        solid_tile(&mut screen, 2 * TILE_SIZE, 2);
+        //Lower X wins even when later in OAM
+        set_sprite(&mut screen, 0, 14, 0, 1, 0);
+        set_sprite(&mut screen, 1, 10, 0, 2, 0);
+        assert_eq!(render(&mut screen, 0)[15],2);
+        //With equal X the first in OAM wins
+        set_sprite(&mut screen, 0, 10, 0, 1, 0);
+        assert_eq!(render(&mut screen, 0)[15],1);
+        //Behind background colors 1-3 only
+        set_sprite(&mut screen, 0, 10, 0, 1, BG_OVER_OBJ_BIT);
+        set_sprite(&mut screen, 1, 40, 0, 1, BG_OVER_OBJ_BIT);
+        solid_tile(&mut screen, 3 * TILE_SIZE, 3);
+        screen.vram[TILE_MAP_0 + 1] = 3;
+        let line = render(&mut screen, 0);
+        assert_eq!(line[10],3);
+        assert_eq!(line[40],1);
+        //With the background off sprites show over blank white as if it were color 0
+        screen.write(BGP_ADDRESS, 0b1111_1111, &mut interrupts);
+        screen.write(LCDC_ADDRESS, screen.lcdc & !BG_ENABLE_BIT, &mut interrupts);
+        let line = render(&mut screen, 0);
+        assert_eq!(line[9],0);
+        assert_eq!(line[10],1);
+    }
+
+    #[test]
+    fn ten_sprites_per_line() {
+        let (mut screen, _) = sprite_screen();
+        solid_tile(&mut screen, TILE_SIZE, 1);
+        for index in 0..12 {
+            set_sprite(&mut screen, index, index as u8 * 10, 0, 1, 0);
+        }
+        let line = render(&mut screen, 0);
+        assert_eq!(line[90],1);
+        assert_eq!(line[100],0);
+        assert_eq!(line[110],0);
+    }
+
+    //Background, window and sprites overlapping, drawn the same by both renderers
+    fn busy_scene(renderer: Renderer) -> (Screen, InterruptController) {
+        let (mut screen, mut interrupts) = sprite_screen();
+        screen.set_renderer(renderer);
+        for tile in 0..4 {
+            for row in 0..8 {
+                screen.vram[tile * TILE_SIZE + row * 2] = (0x35 * (tile + 1) + row * 7) as u8;
+                screen.vram[tile * TILE_SIZE + row * 2 + 1] = (0x1B * (tile + 3) + row * 13) as u8;
+            }
+        }
+        for index in 0..0x400 {
+            screen.vram[TILE_MAP_0 + index] = (index % 3) as u8;
+            screen.vram[TILE_MAP_1 + index] = 3;
+        }
+        screen.write(SCX_ADDRESS, 13, &mut interrupts);
+        screen.write(SCY_ADDRESS, 5, &mut interrupts);
+        screen.write(WY_ADDRESS, 40, &mut interrupts);
+        screen.write(WX_ADDRESS, 87, &mut interrupts);
+        set_sprite(&mut screen, 0, 3, 10, 1, 0);
+        set_sprite(&mut screen, 1, 6, 12, 2, OBP1_BIT | X_FLIP_BIT);
+        set_sprite(&mut screen, 2, 80, 40, 3, BG_OVER_OBJ_BIT);
+        set_sprite(&mut screen, 3, 158, 100, 1, Y_FLIP_BIT);
+        screen.oam[4 * OAM_ENTRY_SIZE..5 * OAM_ENTRY_SIZE].copy_from_slice(&[60, 4, 2, 0]);
+        screen.write(LCDC_ADDRESS, 0x91 | OBJ_ENABLE_BIT | WINDOW_ENABLE_BIT | WINDOW_TILE_MAP_BIT, &mut interrupts);
+        (screen, interrupts)
+    }
+
+    fn run_frame(screen: &mut Screen, interrupts: &mut InterruptController) {
+        screen.tick(LINES_PER_FRAME as u64 * DOTS_PER_LINE as u64, interrupts);
+    }
+
+    #[test]
+    fn pixel_fifo_matches_scanline_renderer() {
+        let (mut scanline, mut interrupts) = busy_scene(Renderer::Scanline);
+        run_frame(&mut scanline, &mut interrupts);
+        let (mut fifo, mut interrupts) = busy_scene(Renderer::PixelFifo);
+        run_frame(&mut fifo, &mut interrupts);
+        for y in 0..HEIGHT {
+            assert_eq!(&fifo.framebuffer[y * WIDTH..(y + 1) * WIDTH], &scanline.framebuffer[y * WIDTH..(y + 1) * WIDTH], "line {}", y);
+        }
+    }
+
+    //Dots spent in mode 3 on the given line
+    fn drawing_length(screen: &mut Screen, interrupts: &mut InterruptController, ly: u8) -> u16 {
+        screen.tick(ly as u64 * DOTS_PER_LINE as u64 + OAM_SCAN_DOTS as u64, interrupts);
+        let mut length = 0;
+        while screen.mode == Mode::Drawing {
+            screen.tick(1, interrupts);
+            length += 1;
+        }
+        length
+    }
+
+    #[test]
+    fn variable_mode_3_length() {
+        use fifo::SPRITE_FETCH_DOTS;
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.set_renderer(Renderer::PixelFifo);
+        assert_eq!(drawing_length(&mut screen, &mut interrupts, 0),DRAWING_DOTS);
+        //Fine scrolling discards pixels at the start of the line
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.set_renderer(Renderer::PixelFifo);
+        screen.write(SCX_ADDRESS, 3, &mut interrupts);
+        assert_eq!(drawing_length(&mut screen, &mut interrupts, 0),DRAWING_DOTS + 3);
+        //Each sprite pauses output while it is fetched
+        let (mut screen, mut interrupts) = sprite_screen();
+        screen.set_renderer(Renderer::PixelFifo);
+        set_sprite(&mut screen, 0, 40, 0, 0, 0);
+        assert_eq!(drawing_length(&mut screen, &mut interrupts, 0),DRAWING_DOTS + SPRITE_FETCH_DOTS as u16);
+        //Starting the window restarts the fetcher
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.set_renderer(Renderer::PixelFifo);
+        screen.write(WX_ADDRESS, 50, &mut interrupts);
+        screen.write(LCDC_ADDRESS, 0x91 | WINDOW_ENABLE_BIT, &mut interrupts);
+        assert!(drawing_length(&mut screen, &mut interrupts, 0) > DRAWING_DOTS);
+    }
+
+    #[test]
+    fn mid_line_palette_change() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.set_renderer(Renderer::PixelFifo);
+        solid_tile(&mut screen, 0, 1);
+        screen.write(BGP_ADDRESS, 0b0000_0100, &mut interrupts);
+        //12 dots of fetching, then one pixel per dot
+        screen.tick(OAM_SCAN_DOTS as u64 + 12 + 50, &mut interrupts);
+        screen.write(BGP_ADDRESS, 0b0000_1000, &mut interrupts);
+        screen.tick(DOTS_PER_LINE as u64, &mut interrupts);
+        assert_eq!(&screen.framebuffer[48..52],&[1, 1, 2, 2]);
+    }
+
+    #[test]
+    fn window_triggers_on_wy_match() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.write(WY_ADDRESS, 2, &mut interrupts);
+        screen.window_triggered = false;
+        screen.tick(DOTS_PER_LINE as u64, &mut interrupts);
+        assert!(!screen.window_triggered);
+        screen.tick(DOTS_PER_LINE as u64, &mut interrupts);
+        assert!(screen.window_triggered);
+        screen.tick(150 * DOTS_PER_LINE as u64, &mut interrupts);
+        assert!(!screen.window_triggered);
+    }
+
+    #[test]
+    fn lcd_off_resets_ly() {
+        let (mut screen, mut interrupts) = enabled_screen();
+        screen.tick(5 * DOTS_PER_LINE as u64, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),5);
+        screen.write(LCDC_ADDRESS, 0x11, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),0);
+        assert_eq!(screen.mode,Mode::HBlank);
+        screen.tick(DOTS_PER_LINE as u64, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),0);
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn lcd_off_raises_no_lyc_interrupt() {
+        let mut screen = Screen::new();
+        let mut interrupts = InterruptController::new();
+        screen.write(LYC_ADDRESS, 0x00, &mut interrupts);
+        screen.write(STAT_ADDRESS, STAT_LYC_SOURCE, &mut interrupts);
+        assert_eq!(screen.read(LY_ADDRESS),0);
+        assert!(!interrupts.is_requested(Interrupt::LcdStat));
+    }
+}
@@ -0,0 +1,222 @@
+use std::collections::VecDeque;
+
+use super::{Screen, Sprite, WIDTH, BG_ENABLE_BIT, OBJ_ENABLE_BIT, BG_TILE_MAP_BIT, WINDOW_TILE_MAP_BIT,
+    TILE_MAP_WIDTH, WINDOW_X_OFFSET, BG_OVER_OBJ_BIT, OBP1_BIT};
+
+//The fetcher spends 2 dots each reading the tile number, the low byte and the high byte
+const FETCH_TILE_DOT: u8 = 2;
+const FETCH_LOW_DOT: u8 = 4;
+const FETCH_HIGH_DOT: u8 = 6;
+//The background fetcher and pixel output pause for this long while a sprite is fetched
+pub const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    obp1: bool,
+    bg_over_obj: bool
+}
+
+const TRANSPARENT: ObjPixel = ObjPixel { color: 0, obp1: false, bg_over_obj: false };
+
+//Mode 3 state of the pixel FIFO renderer
+pub struct PixelFifo {
+    background: VecDeque<u8>,
+    objects: VecDeque<ObjPixel>,
+    //Dots spent on the current background tile fetch
+    fetcher_dots: u8,
+    //Tile column being fetched, counted from the left edge of the background or window
+    fetcher_x: u8,
+    tile_number: u8,
+    tile_low: u8,
+    tile_high: u8,
+    //The first fetch of every line is thrown away
+    first_fetch: bool,
+    fetching_window: bool,
+    //Pixels still to drop at the start of the line for fine scrolling
+    discard: u8,
+    //Next pixel to output
+    lcd_x: u8,
+    //Sprites found by the OAM scan, in OAM order, and whether each has been fetched
+    sprites: Vec<(Sprite, bool)>,
+    sprite_stall: u8,
+    pending_sprite: Option<usize>
+}
+
+impl PixelFifo {
+    pub fn new() -> Self {
+        Self {
+            background: VecDeque::with_capacity(16),
+            objects: VecDeque::with_capacity(16),
+            fetcher_dots: 0,
+            fetcher_x: 0,
+            tile_number: 0,
+            tile_low: 0,
+            tile_high: 0,
+            first_fetch: true,
+            fetching_window: false,
+            discard: 0,
+            lcd_x: 0,
+            sprites: Vec::new(),
+            sprite_stall: 0,
+            pending_sprite: None
+        }
+    }
+}
+
+impl Screen {
+    pub(super) fn start_fifo_line(&mut self) {
+        let sprites = self.scan_oam().into_iter().map(|sprite| (sprite, false)).collect();
+        self.fifo = PixelFifo::new();
+        self.fifo.sprites = sprites;
+        self.fifo.discard = self.scx % 8;
+    }
+
+    pub(super) fn finish_fifo_line(&mut self) {
+        if self.fifo.fetching_window {
+            self.window_line += 1;
+        }
+    }
+
+    //Advance mode 3 by one dot, returning true once the last pixel of the line is out
+    pub(super) fn step_fifo(&mut self) -> bool {
+        if self.fifo.sprite_stall == 0 {
+            self.start_sprite_fetch();
+        }
+        if self.fifo.sprite_stall > 0 {
+            self.fifo.sprite_stall -= 1;
+            if self.fifo.sprite_stall == 0 {
+                self.merge_sprite();
+            }
+            return false;
+        }
+        self.check_window_start();
+        self.output_pixel();
+        self.step_fetcher();
+        self.fifo.lcd_x as usize == WIDTH
+    }
+
+    //Sprites are fetched when output reaches their left edge, lower X first and then in OAM order
+    fn start_sprite_fetch(&mut self) {
+        if self.lcdc & OBJ_ENABLE_BIT == 0 || self.fifo.discard > 0 {
+            return;
+        }
+        let lcd_x = self.fifo.lcd_x as i16;
+        let due = self.fifo.sprites.iter()
+            .enumerate()
+            .filter(|(_, (sprite, fetched))| !fetched && sprite.x <= lcd_x)
+            .min_by_key(|(_, (sprite, _))| sprite.x)
+            .map(|(index, _)| index);
+        if let Some(index) = due {
+            self.fifo.sprites[index].1 = true;
+            self.fifo.pending_sprite = Some(index);
+            self.fifo.sprite_stall = SPRITE_FETCH_DOTS;
+        }
+    }
+
+    //Sprite pixels only replace transparent ones already in the FIFO, so earlier sprites keep priority
+    fn merge_sprite(&mut self) {
+        let Some(index) = self.fifo.pending_sprite.take() else {
+            return;
+        };
+        let sprite = self.fifo.sprites[index].0;
+        let lcd_x = self.fifo.lcd_x as i16;
+        let y = (self.ly as i16 - sprite.y) as u8;
+        for pixel in 0..8 {
+            let screen_x = sprite.x + pixel;
+            if screen_x < lcd_x {
+                continue;
+            }
+            let position = (screen_x - lcd_x) as usize;
+            while self.fifo.objects.len() <= position {
+                self.fifo.objects.push_back(TRANSPARENT);
+            }
+            if self.fifo.objects[position].color == 0 {
+                self.fifo.objects[position] = ObjPixel {
+                    color: self.sprite_pixel(&sprite, pixel as u8, y),
+                    obp1: sprite.attributes & OBP1_BIT != 0,
+                    bg_over_obj: sprite.attributes & BG_OVER_OBJ_BIT != 0
+                };
+            }
+        }
+    }
+
+    //Reaching WX - 7 with the window enabled restarts the fetcher on the window
+    fn check_window_start(&mut self) {
+        if self.fifo.fetching_window || !self.window_visible() {
+            return;
+        }
+        if self.fifo.lcd_x + WINDOW_X_OFFSET >= self.wx {
+            self.fifo.fetching_window = true;
+            self.fifo.background.clear();
+            self.fifo.fetcher_x = 0;
+            self.fifo.fetcher_dots = 0;
+            //With WX below 7 the window is cut off on the left
+            self.fifo.discard = WINDOW_X_OFFSET.saturating_sub(self.wx);
+        }
+    }
+
+    fn output_pixel(&mut self) {
+        let Some(background) = self.fifo.background.pop_front() else {
+            return;
+        };
+        let object = self.fifo.objects.pop_front().unwrap_or(TRANSPARENT);
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return;
+        }
+        //Palettes and enable bits are read as each pixel goes out
+        let background_enabled = self.lcdc & BG_ENABLE_BIT != 0;
+        let background = if background_enabled { background } else { 0 };
+        let object_visible = object.color != 0
+            && self.lcdc & OBJ_ENABLE_BIT != 0
+            && !(object.bg_over_obj && background != 0);
+        let shade = if object_visible {
+            Screen::shade(if object.obp1 { self.obp1 } else { self.obp0 }, object.color)
+        } else if background_enabled {
+            Screen::shade(self.bgp, background)
+        } else {
+            0
+        };
+        self.framebuffer[self.ly as usize * WIDTH + self.fifo.lcd_x as usize] = shade;
+        self.fifo.lcd_x += 1;
+    }
+
+    //VRAM offset of the current row within the tile map, and the row within the tile
+    fn fetcher_position(&self) -> (usize, u8) {
+        if self.fifo.fetching_window {
+            let map = self.tile_map(WINDOW_TILE_MAP_BIT);
+            let y = self.window_line;
+            (map + (y as usize / 8) * TILE_MAP_WIDTH + self.fifo.fetcher_x as usize % TILE_MAP_WIDTH, y % 8)
+        } else {
+            let map = self.tile_map(BG_TILE_MAP_BIT);
+            let y = self.ly.wrapping_add(self.scy);
+            let x = (self.scx / 8).wrapping_add(self.fifo.fetcher_x) as usize % TILE_MAP_WIDTH;
+            (map + (y as usize / 8) * TILE_MAP_WIDTH + x, y % 8)
+        }
+    }
+
+    fn step_fetcher(&mut self) {
+        self.fifo.fetcher_dots += 1;
+        let (map_address, row) = self.fetcher_position();
+        match self.fifo.fetcher_dots {
+            FETCH_TILE_DOT => self.fifo.tile_number = self.vram[map_address],
+            FETCH_LOW_DOT => self.fifo.tile_low = self.vram[self.tile_address(self.fifo.tile_number) + row as usize * 2],
+            FETCH_HIGH_DOT => self.fifo.tile_high = self.vram[self.tile_address(self.fifo.tile_number) + row as usize * 2 + 1],
+            _ => {}
+        }
+        //The fetched row is pushed as soon as the FIFO has run empty
+        if self.fifo.fetcher_dots >= FETCH_HIGH_DOT && self.fifo.background.is_empty() {
+            self.fifo.fetcher_dots = 0;
+            if self.fifo.first_fetch {
+                self.fifo.first_fetch = false;
+                return;
+            }
+            for bit in (0..8).rev() {
+                let color = ((self.fifo.tile_high >> bit) & 0x01) << 1 | (self.fifo.tile_low >> bit) & 0x01;
+                self.fifo.background.push_back(color);
+            }
+            self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::Path;
+
+use super::screen::{WIDTH, HEIGHT};
+
+//RGB colors for the 4 DMG shades, from lightest to darkest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette(pub [[u8; 3]; 4]);
+
+impl Palette {
+    pub const GRAYSCALE: Palette = Palette([[0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA], [0x55, 0x55, 0x55], [0x00, 0x00, 0x00]]);
+    //The green tint of the original DMG screen
+    pub const DMG_GREEN: Palette = Palette([[0x9B, 0xBC, 0x0F], [0x8B, 0xAC, 0x0F], [0x30, 0x62, 0x30], [0x0F, 0x38, 0x0F]]);
+
+    fn color(&self, shade: u8) -> [u8; 3] {
+        self.0[(shade & 0x03) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::GRAYSCALE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Ppm,
+    Png
+}
+
+impl ImageFormat {
+    //Chosen by file extension, ignoring case
+    pub fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "png" => Some(ImageFormat::Png),
+            _ => None
+        }
+    }
+}
+
+//Encode a frame of shades as an image file
+pub fn encode(framebuffer: &[u8], palette: &Palette, format: ImageFormat) -> Vec<u8> {
+    let rgb: Vec<u8> = framebuffer.iter().flat_map(|&shade| palette.color(shade)).collect();
+    match format {
+        ImageFormat::Ppm => encode_ppm(&rgb),
+        ImageFormat::Png => encode_png(&rgb)
+    }
+}
+
+//Binary PPM: a text header followed by the raw RGB bytes
+fn encode_ppm(rgb: &[u8]) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", WIDTH, HEIGHT).into_bytes();
+    data.extend_from_slice(rgb);
+    data
+}
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const PNG_BIT_DEPTH: u8 = 8;
+const PNG_COLOR_TYPE_RGB: u8 = 2;
+const PNG_FILTER_NONE: u8 = 0;
+
+fn encode_png(rgb: &[u8]) -> Vec<u8> {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(WIDTH as u32).to_be_bytes());
+    header.extend_from_slice(&(HEIGHT as u32).to_be_bytes());
+    //Bit depth, color type, then default compression, filter and interlace methods
+    header.extend_from_slice(&[PNG_BIT_DEPTH, PNG_COLOR_TYPE_RGB, 0, 0, 0]);
+
+    //Every scanline starts with its filter type
+    let mut scanlines = Vec::with_capacity(HEIGHT * (WIDTH * 3 + 1));
+    for row in rgb.chunks(WIDTH * 3) {
+        scanlines.push(PNG_FILTER_NONE);
+        scanlines.extend_from_slice(row);
+    }
+
+    let mut data = PNG_SIGNATURE.to_vec();
+    write_chunk(&mut data, b"IHDR", &header);
+    write_chunk(&mut data, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut data, b"IEND", &[]);
+    data
+}
+
+//Length, type, data, then a CRC over the type and data
+fn write_chunk(data: &mut Vec<u8>, chunk_type: &[u8; 4], chunk_data: &[u8]) {
+    data.extend_from_slice(&(chunk_data.len() as u32).to_be_bytes());
+    let start = data.len();
+    data.extend_from_slice(chunk_type);
+    data.extend_from_slice(chunk_data);
+    let crc = crc32(&data[start..]);
+    data.extend_from_slice(&crc.to_be_bytes());
+}
+
+//Deflate with stored blocks only, so no compression but no dependency either
+const ZLIB_HEADER: [u8; 2] = [0x78, 0x01];
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+fn zlib_stored(input: &[u8]) -> Vec<u8> {
+    let blocks = input.len().div_ceil(STORED_BLOCK_SIZE).max(1);
+    let mut data = Vec::with_capacity(input.len() + blocks * 5 + 6);
+    data.extend_from_slice(&ZLIB_HEADER);
+    for index in 0..blocks {
+        let block = &input[index * STORED_BLOCK_SIZE..input.len().min((index + 1) * STORED_BLOCK_SIZE)];
+        //BFINAL in bit 0, BTYPE 00 for a stored block
+        data.push((index == blocks - 1) as u8);
+        let length = block.len() as u16;
+        data.extend_from_slice(&length.to_le_bytes());
+        data.extend_from_slice(&(!length).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(input).to_be_bytes());
+    data
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULO: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MODULO;
+        b = (b + a) % MODULO;
+    }
+    (b << 16) | a
+}
+
+//CRC-32 with the reflected polynomial used by PNG and zlib
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut crc = n as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { 0xEDB88320 ^ (crc >> 1) } else { crc >> 1 };
+            bit += 1;
+        }
+        table[n] = crc;
+        n += 1;
+    }
+    table
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(0xFFFFFFFF, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"),0xAE426082);
+        assert_eq!(crc32(b"123456789"),0xCBF43926);
+        assert_eq!(adler32(b"Wikipedia"),0x11E60398);
+    }
+
+    #[test]
+    fn zlib_stored_blocks() {
+        let input = vec![0x5A; STORED_BLOCK_SIZE + 10];
+        let data = zlib_stored(&input);
+        assert_eq!(data[0..2],ZLIB_HEADER);
+        //First block is full and not final
+        assert_eq!(data[2..7],[0x00, 0xFF, 0xFF, 0x00, 0x00]);
+        let second = 7 + STORED_BLOCK_SIZE;
+        assert_eq!(data[second..second + 5],[0x01, 0x0A, 0x00, 0xF5, 0xFF]);
+        assert_eq!(data.len(),second + 5 + 10 + 4);
+        assert_eq!(data[data.len() - 4..],adler32(&input).to_be_bytes());
+    }
+
+    #[test]
+    fn ppm_uses_palette() {
+        let mut framebuffer = vec![0; WIDTH * HEIGHT];
+        framebuffer[1] = 3;
+        let data = encode(&framebuffer, &Palette::DMG_GREEN, ImageFormat::Ppm);
+        let header = b"P6\n160 144\n255\n";
+        assert_eq!(data[..header.len()],header[..]);
+        assert_eq!(data.len(),header.len() + WIDTH * HEIGHT * 3);
+        assert_eq!(data[header.len()..header.len() + 6],[0x9B, 0xBC, 0x0F, 0x0F, 0x38, 0x0F]);
+    }
+
+    #[test]
+    fn png_layout() {
+        let framebuffer = vec![2; WIDTH * HEIGHT];
+        let data = encode(&framebuffer, &Palette::GRAYSCALE, ImageFormat::Png);
+        assert_eq!(data[0..8],PNG_SIGNATURE);
+        //IHDR
+        assert_eq!(data[8..16],[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(data[16..29],[0, 0, 0, 160, 0, 0, 0, 144, 8, 2, 0, 0, 0]);
+        assert_eq!(data[29..33],crc32(&data[12..29]).to_be_bytes());
+        //IDAT holds the stored scanlines, the first one starting after the zlib and block headers
+        assert_eq!(data[37..41],*b"IDAT");
+        assert_eq!(data[41..43],ZLIB_HEADER);
+        assert_eq!(data[48..52],[PNG_FILTER_NONE, 0x55, 0x55, 0x55]);
+        assert_eq!(data[data.len() - 12..],[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+    }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(ImageFormat::from_path(Path::new("shot.PNG")),Some(ImageFormat::Png));
+        assert_eq!(ImageFormat::from_path(Path::new("out/shot.ppm")),Some(ImageFormat::Ppm));
+        assert_eq!(ImageFormat::from_path(Path::new("shot.bmp")),None);
+        assert_eq!(ImageFormat::from_path(Path::new("shot")),None);
+    }
+}
@@ -0,0 +1,119 @@
+use super::interrupts::{Interrupt, InterruptController};
+
+const SB_ADDRESS: u16 = 0xFF01;
+const SC_ADDRESS: u16 = 0xFF02;
+
+const TRANSFER_START_BIT: u8 = 0x80;
+const INTERNAL_CLOCK_BIT: u8 = 0x01;
+//CGB only, selects the fast clock
+const CLOCK_SPEED_BIT: u8 = 0x02;
+
+//The internal clock shifts one bit every 128 M-cycles, 8192 Hz
+const M_CYCLES_PER_BIT: u64 = 128;
+const FAST_M_CYCLES_PER_BIT: u64 = 4;
+
+//SB and SC at 0xFF01-0xFF02. There is no link partner, so every bit shifted in is 1
+pub struct Serial {
+    data: u8,
+    control: u8,
+    cgb: bool,
+    bits_remaining: u8,
+    //M-cycles towards shifting the next bit
+    cycles: u64
+}
+
+impl Serial {
+    pub fn new(cgb: bool) -> Self {
+        Self {
+            data: 0,
+            control: 0,
+            cgb,
+            bits_remaining: 0,
+            cycles: 0
+        }
+    }
+
+    fn unused_bits(&self) -> u8 {
+        if self.cgb { 0x7C } else { 0x7E }
+    }
+
+    fn m_cycles_per_bit(&self) -> u64 {
+        if self.cgb && self.control & CLOCK_SPEED_BIT != 0 { FAST_M_CYCLES_PER_BIT } else { M_CYCLES_PER_BIT }
+    }
+
+    //Only transfers clocked by this Game Boy make progress, an external clock never arrives
+    pub fn tick(&mut self, m_cycles: u64, interrupts: &mut InterruptController) {
+        if self.bits_remaining == 0 {
+            return;
+        }
+        self.cycles += m_cycles;
+        while self.bits_remaining > 0 && self.cycles >= self.m_cycles_per_bit() {
+            self.cycles -= self.m_cycles_per_bit();
+            self.data = (self.data << 1) | 0x01;
+            self.bits_remaining -= 1;
+            if self.bits_remaining == 0 {
+                self.control &= !TRANSFER_START_BIT;
+                interrupts.request(Interrupt::Serial);
+            }
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            SB_ADDRESS => self.data,
+            SC_ADDRESS => self.control | self.unused_bits(),
+            _ => 0xFF
+        }
+    }
+
+    pub fn set_register(&mut self, address: u16, value: u8) {
+        match address {
+            SB_ADDRESS => self.data = value,
+            SC_ADDRESS => self.control = value & !self.unused_bits(),
+            _ => {}
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match address {
+            SB_ADDRESS => self.data = value,
+            SC_ADDRESS => {
+                self.control = value & !self.unused_bits();
+                let internal = value & INTERNAL_CLOCK_BIT != 0;
+                self.bits_remaining = if value & TRANSFER_START_BIT != 0 && internal { 8 } else { 0 };
+                self.cycles = 0;
+            },
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn internal_clock_transfer_completes() {
+        let mut serial = Serial::new(false);
+        let mut interrupts = InterruptController::new();
+        serial.write(SB_ADDRESS,0x00);
+        serial.write(SC_ADDRESS,0x81);
+        assert_eq!(serial.read(SC_ADDRESS),0xFF);
+        serial.tick(8 * M_CYCLES_PER_BIT - 1, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Serial));
+        serial.tick(1, &mut interrupts);
+        assert!(interrupts.is_requested(Interrupt::Serial));
+        assert_eq!(serial.read(SB_ADDRESS),0xFF);
+        assert_eq!(serial.read(SC_ADDRESS),0x7F);
+    }
+
+    #[test]
+    fn external_clock_never_completes() {
+        let mut serial = Serial::new(false);
+        let mut interrupts = InterruptController::new();
+        serial.write(SC_ADDRESS,0x80);
+        serial.tick(100_000, &mut interrupts);
+        assert!(!interrupts.is_requested(Interrupt::Serial));
+        assert_eq!(serial.read(SC_ADDRESS),0xFE);
+    }
+}
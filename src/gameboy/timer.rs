@@ -0,0 +1,247 @@
+use super::interrupts::{Interrupt, InterruptController};
+
+const DIV_ADDRESS: u16 = 0xFF04;
+const TIMA_ADDRESS: u16 = 0xFF05;
+const TMA_ADDRESS: u16 = 0xFF06;
+const TAC_ADDRESS: u16 = 0xFF07;
+
+//Only the lower 3 bits of TAC exist
+const TAC_UNUSED_BITS: u8 = 0xF8;
+const TAC_ENABLE_BIT: u8 = 0x04;
+
+const T_CYCLES_PER_M_CYCLE: u16 = 4;
+
+//DIV, TIMA, TMA and TAC at 0xFF04-0xFF07
+pub struct Timer {
+    //16 bit counter incremented every T-cycle, DIV is its upper byte
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    //TIMA overflowed during the last M-cycle and reads 0 until TMA is loaded in the next one
+    overflowed: bool,
+    //TMA was loaded into TIMA during the last M-cycle
+    reloading: bool
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+            reloading: false
+        }
+    }
+
+    pub fn set_register(&mut self, address: u16, value: u8) {
+        match address {
+            DIV_ADDRESS => self.counter = (value as u16) << 8,
+            TIMA_ADDRESS => self.tima = value,
+            TMA_ADDRESS => self.tma = value,
+            TAC_ADDRESS => self.tac = value & !TAC_UNUSED_BITS,
+            _ => {}
+        }
+    }
+
+    //TIMA counts on falling edges of one counter bit ANDed with the enable bit, so anything making this
+    //signal fall counts, not just the counter moving on
+    fn signal(&self) -> bool {
+        let bit = match self.tac & 0x03 {
+            0x00 => 9,
+            0x01 => 3,
+            0x02 => 5,
+            _ => 7
+        };
+        self.tac & TAC_ENABLE_BIT != 0 && (self.counter >> bit) & 0x01 != 0
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflowed = overflow;
+    }
+
+    //Count if the signal fell from the given previous state
+    fn detect_falling_edge(&mut self, previous: bool) {
+        if previous && !self.signal() {
+            self.increment_tima();
+        }
+    }
+
+    fn step(&mut self, interrupts: &mut InterruptController) {
+        //The reload and interrupt happen one M-cycle after the overflow
+        self.reloading = false;
+        if self.overflowed {
+            self.overflowed = false;
+            self.tima = self.tma;
+            self.reloading = true;
+            interrupts.request(Interrupt::Timer);
+        }
+        let previous = self.signal();
+        self.counter = self.counter.wrapping_add(T_CYCLES_PER_M_CYCLE);
+        self.detect_falling_edge(previous);
+    }
+
+    pub fn tick(&mut self, m_cycles: u64, interrupts: &mut InterruptController) {
+        for _ in 0..m_cycles {
+            self.step(interrupts);
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            DIV_ADDRESS => (self.counter >> 8) as u8,
+            TIMA_ADDRESS => self.tima,
+            TMA_ADDRESS => self.tma,
+            TAC_ADDRESS => self.tac | TAC_UNUSED_BITS,
+            _ => 0xFF
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        let previous = self.signal();
+        match address {
+            //Any write clears the whole internal counter, which counts as a falling edge if the bit was set
+            DIV_ADDRESS => self.counter = 0,
+            //Writing TIMA after an overflow cancels the reload and interrupt, while writes in the
+            //reload cycle itself are overwritten by TMA
+            TIMA_ADDRESS if !self.reloading => {
+                self.tima = value;
+                self.overflowed = false;
+            },
+            //The reload cycle copies TMA as it is being written
+            TMA_ADDRESS => {
+                self.tma = value;
+                if self.reloading {
+                    self.tima = value;
+                }
+            },
+            //Disabling the timer or switching to a bit that is clear both make the signal fall
+            TAC_ADDRESS => self.tac = value & !TAC_UNUSED_BITS,
+            _ => {}
+        }
+        self.detect_falling_edge(previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timer_with_tac(tac: u8) -> (Timer, InterruptController) {
+        let mut timer = Timer::new();
+        timer.write(TAC_ADDRESS, tac);
+        (timer, InterruptController::new())
+    }
+
+    //div_write
+    #[test]
+    fn div_counts_and_resets() {
+        let (mut timer, mut interrupts) = timer_with_tac(0x00);
+        timer.tick(64, &mut interrupts);
+        assert_eq!(timer.read(DIV_ADDRESS),0x01);
+        timer.write(DIV_ADDRESS,0xFF);
+        assert_eq!(timer.read(DIV_ADDRESS),0x00);
+        timer.tick(63, &mut interrupts);
+        assert_eq!(timer.read(DIV_ADDRESS),0x00);
+        timer.tick(1, &mut interrupts);
+        assert_eq!(timer.read(DIV_ADDRESS),0x01);
+    }
+
+    //tim00, tim01, tim10, tim11
+    #[test]
+    fn tima_frequencies() {
+        for (tac, m_cycles) in [(0x04, 256), (0x05, 4), (0x06, 16), (0x07, 64)] {
+            let (mut timer, mut interrupts) = timer_with_tac(tac);
+            timer.tick(m_cycles - 1, &mut interrupts);
+            assert_eq!(timer.read(TIMA_ADDRESS),0, "TAC {:#04x}", tac);
+            timer.tick(1, &mut interrupts);
+            assert_eq!(timer.read(TIMA_ADDRESS),1, "TAC {:#04x}", tac);
+        }
+    }
+
+    //tima_reload
+    #[test]
+    fn overflow_reloads_one_cycle_late() {
+        let (mut timer, mut interrupts) = timer_with_tac(0x05);
+        timer.write(TMA_ADDRESS,0xFE);
+        timer.write(TIMA_ADDRESS,0xFF);
+        timer.tick(4, &mut interrupts);
+        assert_eq!(timer.read(TIMA_ADDRESS),0x00);
+        assert!(!interrupts.is_requested(Interrupt::Timer));
+        timer.tick(1, &mut interrupts);
+        assert_eq!(timer.read(TIMA_ADDRESS),0xFE);
+        assert!(interrupts.is_requested(Interrupt::Timer));
+    }
+
+    //tima_write_reloading
+    #[test]
+    fn tima_write_around_reload() {
+        //Writing in the cycle after the overflow cancels the reload and interrupt
+        let (mut timer, mut interrupts) = timer_with_tac(0x05);
+        timer.write(TMA_ADDRESS,0xFE);
+        timer.write(TIMA_ADDRESS,0xFF);
+        timer.tick(4, &mut interrupts);
+        timer.write(TIMA_ADDRESS,0x12);
+        timer.tick(1, &mut interrupts);
+        assert_eq!(timer.read(TIMA_ADDRESS),0x12);
+        assert!(!interrupts.is_requested(Interrupt::Timer));
+
+        //Writing in the reload cycle is ignored
+        let (mut timer, mut interrupts) = timer_with_tac(0x05);
+        timer.write(TMA_ADDRESS,0xFE);
+        timer.write(TIMA_ADDRESS,0xFF);
+        timer.tick(5, &mut interrupts);
+        timer.write(TIMA_ADDRESS,0x12);
+        assert_eq!(timer.read(TIMA_ADDRESS),0xFE);
+        assert!(interrupts.is_requested(Interrupt::Timer));
+    }
+
+    //tma_write_reloading
+    #[test]
+    fn tma_write_in_reload_cycle_reaches_tima() {
+        let (mut timer, mut interrupts) = timer_with_tac(0x05);
+        timer.write(TMA_ADDRESS,0xFE);
+        timer.write(TIMA_ADDRESS,0xFF);
+        timer.tick(5, &mut interrupts);
+        timer.write(TMA_ADDRESS,0x34);
+        assert_eq!(timer.read(TIMA_ADDRESS),0x34);
+        timer.tick(1, &mut interrupts);
+        timer.write(TMA_ADDRESS,0x56);
+        assert_eq!(timer.read(TIMA_ADDRESS),0x34);
+    }
+
+    //div_trigger
+    #[test]
+    fn div_write_while_bit_is_set_counts() {
+        let (mut timer, mut interrupts) = timer_with_tac(0x05);
+        timer.tick(2, &mut interrupts);
+        assert_eq!(timer.read(TIMA_ADDRESS),0);
+        timer.write(DIV_ADDRESS,0x00);
+        assert_eq!(timer.read(TIMA_ADDRESS),1);
+        //With the bit clear nothing happens
+        timer.tick(1, &mut interrupts);
+        timer.write(DIV_ADDRESS,0x00);
+        assert_eq!(timer.read(TIMA_ADDRESS),1);
+    }
+
+    //rapid_toggle
+    #[test]
+    fn tac_writes_making_the_signal_fall_count() {
+        let (mut timer, mut interrupts) = timer_with_tac(0x05);
+        timer.tick(2, &mut interrupts);
+        //Disabling the timer while the bit is set
+        timer.write(TAC_ADDRESS,0x01);
+        assert_eq!(timer.read(TIMA_ADDRESS),1);
+        //Enabling does not count
+        timer.write(TAC_ADDRESS,0x05);
+        assert_eq!(timer.read(TIMA_ADDRESS),1);
+        //Switching from bit 3, which is set, to bit 9, which is clear
+        timer.write(TAC_ADDRESS,0x04);
+        assert_eq!(timer.read(TIMA_ADDRESS),2);
+        assert_eq!(timer.read(TAC_ADDRESS),0xFC);
+    }
+}
@@ -1,18 +1,203 @@
-use gameboy::Gameboy;
-mod gameboy;
+use gameboyemu::gameboy::{Gameboy, ImageFormat, Model};
 use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+const USAGE: &str = "Usage: rustyboy [OPTIONS] <ROM>
+
+Options:
+  --boot-rom <FILE>     Run this boot ROM first instead of starting at the cartridge entry point
+  --model <MODEL>       Hardware to emulate: dmg0, dmg, mgb, sgb or cgb [default: dmg]
+  --headless            Run as fast as possible instead of in real time
+  --frames <N>          Exit after N frames
+  --speed <FACTOR>      Multiple of real time to run at [default: 1]
+  --save-dir <DIR>      Directory for battery save files [default: next to the ROM]
+  --trace               Print the CPU state before every instruction to stdout
+  --screenshot <FILE>   Save the last frame as .png or .ppm on exit, after 60 frames unless --frames is given
+  --help                Print this help
+
+Exit codes: 0 on success, 1 when the emulator fails, 2 for invalid arguments";
+
+//Frames run before taking a screenshot when --frames is not given
+const DEFAULT_SCREENSHOT_FRAMES: u64 = 60;
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    rom: String,
+    boot_rom: Option<String>,
+    model: Model,
+    headless: bool,
+    frames: Option<u64>,
+    speed: f64,
+    save_dir: Option<String>,
+    trace: bool,
+    screenshot: Option<String>
+}
+
+//Ok(None) when help was asked for
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Options>, String> {
+    let mut rom = None;
+    let mut boot_rom = None;
+    let mut model = Model::default();
+    let mut headless = false;
+    let mut frames = None;
+    let mut speed = 1.0;
+    let mut save_dir = None;
+    let mut trace = false;
+    let mut screenshot = None;
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(None),
+            "--boot-rom" => boot_rom = Some(value()?),
+            "--model" => {
+                let name = value()?;
+                model = Model::from_name(&name).ok_or(format!("Unknown model {}", name))?;
+            },
+            "--headless" => headless = true,
+            "--frames" => {
+                let count = value()?;
+                frames = Some(count.parse().map_err(|_| format!("Invalid frame count {}", count))?);
+            },
+            "--speed" => {
+                let factor = value()?;
+                speed = factor.parse().ok()
+                    .filter(|speed: &f64| speed.is_finite() && *speed > 0.0)
+                    .ok_or(format!("Invalid speed {}", factor))?;
+            },
+            "--save-dir" => save_dir = Some(value()?),
+            "--trace" => trace = true,
+            "--screenshot" => {
+                let path = value()?;
+                ImageFormat::from_path(Path::new(&path)).ok_or(format!("Screenshots must be .png or .ppm files: {}", path))?;
+                screenshot = Some(path);
+            },
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_some() => return Err(format!("Unexpected argument {}", arg)),
+            _ => rom = Some(arg)
+        }
+    }
+    let rom = rom.ok_or("No ROM given")?;
+    if screenshot.is_some() && frames.is_none() {
+        frames = Some(DEFAULT_SCREENSHOT_FRAMES);
+    }
+    Ok(Some(Options { rom, boot_rom, model, headless, frames, speed, save_dir, trace, screenshot }))
+}
 
 fn main() {
-    let mut gameboy = Gameboy::new();
-    let path = env::current_dir();
-    match path {
-        Ok(v) => {
-            println!("The current directory is {}", v.display());
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", USAGE);
+            return;
+        },
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            process::exit(EXIT_USAGE);
         }
-        Err(e) => {println!("{}:Could not read path!",e)}
+    };
+    //process::exit skips destructors, so only exit once the Gameboy is gone and its trace output flushed
+    process::exit(run(&options));
+}
+
+//Run the emulator as the options ask and return the exit code
+fn run(options: &Options) -> i32 {
+    let mut gameboy = Gameboy::with_model(options.model);
+    if let Some(ref dir) = options.save_dir {
+        if let Err(error) = fs::create_dir_all(dir) {
+            eprintln!("{}: {}", dir, error);
+            return EXIT_FAILURE;
+        }
+        gameboy.set_save_dir(dir);
+    }
+    if let Err(error) = gameboy.load_cartridge(&options.rom) {
+        eprintln!("{}: {}", options.rom, error);
+        return EXIT_FAILURE;
+    }
+
+    //Without a boot ROM, start the cartridge directly in its post boot state
+    match options.boot_rom {
+        Some(ref path) => {
+            if let Err(error) = gameboy.load_boot_rom(path) {
+                eprintln!("{}: {}", path, error);
+                return EXIT_FAILURE;
+            }
+        },
+        None => gameboy.skip_boot()
     }
-    
 
-    gameboy.load_boot_rom("roms/dmg_boot.bin").expect("Failed to load boot ROM");
-    gameboy.run();
+    if options.trace {
+        gameboy.set_trace(io::BufWriter::new(io::stdout()));
+    }
+    gameboy.set_speed(if options.headless { None } else { Some(options.speed) });
+
+    if let Err(error) = gameboy.run_for(options.frames) {
+        eprintln!("{}", error);
+        //Keep the progress made before the failure
+        if let Err(error) = gameboy.flush_save() {
+            eprintln!("Could not write save file: {}", error);
+        }
+        return EXIT_FAILURE;
+    }
+    let mut exit_code = EXIT_SUCCESS;
+    if let Some(ref path) = options.screenshot {
+        if let Err(error) = gameboy.screenshot(path) {
+            eprintln!("{}: {}", path, error);
+            exit_code = EXIT_FAILURE;
+        }
+    }
+    if let Err(error) = gameboy.flush_save() {
+        eprintln!("Could not write save file: {}", error);
+        exit_code = EXIT_FAILURE;
+    }
+    exit_code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<Options>, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_options() {
+        let options = parse(&["--model", "cgb", "game.gb", "--headless", "--frames", "10", "--save-dir", "saves", "--trace"])
+            .unwrap().unwrap();
+        assert_eq!(options.rom,"game.gb");
+        assert_eq!(options.model,Model::Cgb);
+        assert!(options.headless);
+        assert_eq!(options.frames,Some(10));
+        assert_eq!(options.save_dir.as_deref(),Some("saves"));
+        assert!(options.trace);
+        assert_eq!(options.boot_rom,None);
+        assert_eq!(options.speed,1.0);
+    }
+
+    #[test]
+    fn screenshot_defaults_frame_count() {
+        let options = parse(&["game.gb", "--screenshot", "shot.png"]).unwrap().unwrap();
+        assert_eq!(options.frames,Some(DEFAULT_SCREENSHOT_FRAMES));
+        let options = parse(&["game.gb", "--screenshot", "shot.png", "--frames", "5"]).unwrap().unwrap();
+        assert_eq!(options.frames,Some(5));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert_eq!(parse(&["game.gb", "--help"]),Ok(None));
+        assert!(parse(&[]).is_err());
+        assert!(parse(&["game.gb", "other.gb"]).is_err());
+        assert!(parse(&["game.gb", "--model", "gba"]).is_err());
+        assert!(parse(&["game.gb", "--speed", "0"]).is_err());
+        assert!(parse(&["game.gb", "--frames"]).is_err());
+        assert!(parse(&["game.gb", "--fast"]).is_err());
+        assert!(parse(&["game.gb", "--screenshot", "shot.bmp"]).is_err());
+    }
 }